@@ -9,24 +9,29 @@ parser!(pub grammar parser() for str {
 
     // top level rule
     pub rule script() -> Vec<Token>
-        = WHITESPACE() f:(constant() / comment() / class() / function())* WHITESPACE() { f }
+        = WHITESPACE() f:(import() / constant() / class() / function() / statement())* WHITESPACE() { f }
 
     rule statement() -> Token
-        = WHITESPACE() s:(
-            comment() /
+        = WHITESPACE() p:position!() s:(
             var() /
             call() /
             rtn() /
+            brk() /
+            cont() /
             loop_while() /
             loop_for() /
             loop_for_each() /
             if_else() /
             assignment() /
             dot_chain()
-        ) WHITESPACE() { s }
+        ) WHITESPACE() { Token::Located(p, Box::new(s)) }
 
-    rule comment() -> Token
-        = "--" s:$([' ' | ',' |'a'..='z' | 'A'..='Z' | '0'..='9']*) NEWLINES() { Token::Comment(s.to_owned()) }
+    //==============================================================================================
+    // IMPORTS
+
+    // import "math/vector" or import vector, optionally under another name with as
+    rule import() -> Token
+        = "import" !IDENTCHAR() _ path:(("\"" p:$([^'"']+) "\"" { p }) / $(IDENTCHAR()+)) alias:(_ "as" _ a:identifier() { a.to_string() })? WHITESPACE() { Token::Import(path.to_owned(), alias) }
 
     //==============================================================================================
     // FUNCTIONS
@@ -52,36 +57,40 @@ parser!(pub grammar parser() for str {
         = quiet!{args:((_ e:identifier() _ {e}) ** ",") { args } }
 
     rule rtn() -> Token
-        = "return" _ e:expression() { Token::Return(Box::new(e)) }
+        = "return" !IDENTCHAR() _ e:expression() { Token::Return(Box::new(e)) }
 
     //==============================================================================================
     // CLASS
 
     rule class() -> Token
-        = "class" _ name:identifier() WHITESPACE() body:(WHITESPACE() item:(comment() / var() / function()) WHITESPACE() { item })* WHITESPACE() "end" WHITESPACE() { Token::Class(name.to_string(), body) }
+        = "class" _ name:identifier() parent:(_ "extends" _ p:qualified_name() { p })? WHITESPACE() body:(WHITESPACE() p:position!() item:(static_member() / var() / function()) WHITESPACE() { Token::Located(p, Box::new(item)) })* WHITESPACE() "end" WHITESPACE() { Token::Class(name.to_string(), parent, body) }
+
 
+    // class members that belong to the class instead of each object
+    rule static_member() -> Token
+        = "static" _ item:(var() / function()) { Token::Static(Box::new(item)) }
 
     //==============================================================================================
     // VARIABLES
 
     // variable declaration either with a value or default to null
     rule var() -> Token
-        = "var" _ i:identifier() WHITESPACE() "=" WHITESPACE() e:expression() {  Token::Variable(Box::new(i), Box::new(e)) }
-        / "var" _ i:identifier() { Token::Variable(Box::new(i), Box::new(Token::Null)) }
+        = "var" !IDENTCHAR() _ i:identifier() WHITESPACE() "=" WHITESPACE() e:expression() {  Token::Variable(Box::new(i), Box::new(e)) }
+        / "var" !IDENTCHAR() _ i:identifier() { Token::Variable(Box::new(i), Box::new(Token::Null)) }
 
     // existing variable assignment
     rule assignment() -> Token
         = left:(dot_chain() / array_index() / identifier()) WHITESPACE() "=" WHITESPACE() r:expression() {  Token::Assign(Box::new(left), Box::new(r)) }
 
     rule constant() -> Token
-        = "const" _ i:identifier() WHITESPACE() "=" WHITESPACE() e:expression() NEWLINES() {  Token::Constant(Box::new(i), Box::new(e)) }
+        = "const" !IDENTCHAR() _ i:identifier() WHITESPACE() "=" WHITESPACE() e:expression() WHITESPACE() {  Token::Constant(Box::new(i), Box::new(e)) }
 
 
     //==============================================================================================
     // CHAIN
 
     rule dot_chain() -> Token
-        = i:dot_chain_item() "." chain:((e:dot_chain_item() {e}) ** ".") { Token::DotChain(Box::new(i), chain) }
+        = i:(group() / dot_chain_item()) "." chain:((e:dot_chain_item() {e}) ++ ".") { Token::DotChain(Box::new(i), chain) }
 
     rule dot_chain_item() -> Token
         = item:(call() / array_index() / identifier()) { item }
@@ -90,6 +99,12 @@ parser!(pub grammar parser() for str {
     //==============================================================================================
     // LOOPS
 
+    rule brk() -> Token
+        = "break" !IDENTCHAR() { Token::Break }
+
+    rule cont() -> Token
+        = "continue" !IDENTCHAR() { Token::Continue }
+
     rule loop_while() -> Token
         = "while" _ e:expression() _ "do" _ stmts:statement()* _ "end" { Token::WhileLoop(Box::new(e), stmts) }
 
@@ -98,32 +113,52 @@ parser!(pub grammar parser() for str {
         / "for" _ i:identifier() _ "=" _ start:expression() _ "to" _ end:expression() _ "step" _ step:expression() _ "do" WHITESPACE() stmts:statement()* WHITESPACE() "end" { Token::ForI(Box::new(i), Box::new(start), Box::new(step), Box::new(end), stmts) }
 
     rule loop_for_each() -> Token
-        = "for" _ i:identifier() _ "in" _ e:expression() _ "do" _ stmts:statement()* _ "end" { Token::ForEach(Box::new(i), Box::new(e), stmts) }
+        = "for" _ k:identifier() _ "," _ v:identifier() _ "in" _ e:expression() _ "do" _ stmts:statement()* _ "end" { Token::ForEachPair(Box::new(k), Box::new(v), Box::new(e), stmts) }
+        / "for" _ i:identifier() _ "in" _ e:expression() _ "do" _ stmts:statement()* _ "end" { Token::ForEach(Box::new(i), Box::new(e), stmts) }
 
     //==============================================================================================
     // IF
 
     rule if_else() -> Token
         = "if" _ e:expression() WHITESPACE() "then" WHITESPACE() then_body:statement()* WHITESPACE() WHITESPACE()
-            else_body:("else" WHITESPACE() s:statement()* WHITESPACE()  { s })? WHITESPACE() "end"
-        { Token::IfElse(Box::new(e), then_body, else_body) }
+            else_ifs:else_if()*
+            else_body:("else" !IDENTCHAR() WHITESPACE() s:statement()* WHITESPACE()  { s })? WHITESPACE() "end"
+        {
+            let mut branches = vec![(e, then_body)];
+            branches.extend(else_ifs);
+            Token::IfElse(branches, else_body)
+        }
+
+    rule else_if() -> (Token, Vec<Token>)
+        = "elseif" _ e:expression() WHITESPACE() "then" WHITESPACE() body:statement()* WHITESPACE() { (e, body) }
 
     //==============================================================================================
     // EXPRESSIONS
 
     rule expression() -> Token = precedence!{
-        a:@ _ "==" _ b:(@) { Token::Eq(Box::new(a), Box::new(b)) }
-        a:@ _ "!=" _ b:(@) { Token::Ne(Box::new(a), Box::new(b)) }
-        a:@ _ "<"  _ b:(@) { Token::Lt(Box::new(a), Box::new(b)) }
-        a:@ _ "<=" _ b:(@) { Token::Le(Box::new(a), Box::new(b)) }
-        a:@ _ ">"  _ b:(@) { Token::Gt(Box::new(a), Box::new(b)) }
-        a:@ _ ">=" _ b:(@) { Token::Ge(Box::new(a), Box::new(b)) }
+        a:(@) _ "or" !IDENTCHAR() _ b:@ { Token::Or(Box::new(a), Box::new(b)) }
+        --
+        a:(@) _ "and" !IDENTCHAR() _ b:@ { Token::And(Box::new(a), Box::new(b)) }
+        --
+        "not" !IDENTCHAR() _ a:@ { Token::Not(Box::new(a)) }
+        --
+        a:(@) _ "==" _ b:@ { Token::Eq(Box::new(a), Box::new(b)) }
+        a:(@) _ "!=" _ b:@ { Token::Ne(Box::new(a), Box::new(b)) }
+        a:(@) _ "<"  _ b:@ { Token::Lt(Box::new(a), Box::new(b)) }
+        a:(@) _ "<=" _ b:@ { Token::Le(Box::new(a), Box::new(b)) }
+        a:(@) _ ">"  _ b:@ { Token::Gt(Box::new(a), Box::new(b)) }
+        a:(@) _ ">=" _ b:@ { Token::Ge(Box::new(a), Box::new(b)) }
+        --
+        a:(@) _ "+" _ b:@ { Token::Add(Box::new(a), Box::new(b)) }
+        a:(@) _ "-" !"-" _ b:@ { Token::Sub(Box::new(a), Box::new(b)) }
+        --
+        a:(@) _ "*" _ b:@ { Token::Mul(Box::new(a), Box::new(b)) }
+        a:(@) _ "//" _ b:@ { Token::IntDiv(Box::new(a), Box::new(b)) }
+        a:(@) _ "/" _ b:@ { Token::Div(Box::new(a), Box::new(b)) }
+        a:(@) _ "%" _ b:@ { Token::Mod(Box::new(a), Box::new(b)) }
         --
-        a:@ _ "+" _ b:(@) { Token::Add(Box::new(a), Box::new(b)) }
-        a:@ _ "-" _ b:(@) { Token::Sub(Box::new(a), Box::new(b)) }
+        "-" !"-" _ a:@ { Token::Neg(Box::new(a)) }
         --
-        a:@ _ "*" _ b:(@) { Token::Mul(Box::new(a), Box::new(b)) }
-        a:@ _ "/" _ b:(@) { Token::Div(Box::new(a), Box::new(b)) }
         a:@ _ "^" _ b:(@) { Token::Pow(Box::new(a), Box::new(b)) }
         --
         l:literal() { l }
@@ -135,6 +170,7 @@ parser!(pub grammar parser() for str {
         / list()
         / dictionary()
         / dot_chain()
+        / group()
         / array_index()
         / lambda() // this needs to come before call
         / call()
@@ -145,17 +181,40 @@ parser!(pub grammar parser() for str {
         / identifier() // this is greedy and must always come last
 
 
+    // an expression in parentheses, cached because a dot chain tries it before the group on its own
+    #[cache]
+    rule group() -> Token
+        = "(" WHITESPACE() e:expression() WHITESPACE() ")" { e }
+
     rule identifier() -> Token
         = n:$(['a'..='z' | 'A'..='Z' | '_']['a'..='z' | 'A'..='Z' | '0'..='9' | '_']*) { Token::Identifier(n.to_owned()) }
 
+    // triple quoted strings can span lines and are used exactly as written
     rule string() -> Token
-        = "\""  n:$([^'"']*) "\""  { Token::String(n.to_owned()) }
+        = "\"\"\"" n:$((!"\"\"\"" [_])*) "\"\"\"" { Token::String(n.to_owned()) }
+        / "\"" parts:string_part()* "\"" { interpolate(parts) }
+
+    rule string_part() -> Token
+        = "${" WHITESPACE() e:expression() WHITESPACE() "}" { e }
+        / n:$([^'"' | '\\' | '$']+) { Token::String(n.to_owned()) }
+        / "$" !"{" { Token::String(String::from("$")) }
+        / "\\" c:escape() { Token::String(c.to_string()) }
+
+    rule escape() -> char
+        = "n" { '\n' }
+        / "t" { '\t' }
+        / "r" { '\r' }
+        / "0" { '\0' }
+        / "\\" { '\\' }
+        / "\"" { '"' }
+        / "$" { '$' }
+        / "u{" n:$(['0'..='9' | 'a'..='f' | 'A'..='F']*<1,6>) "}" {? u32::from_str_radix(n, 16).ok().and_then(char::from_u32).ok_or("unicode escape") }
 
     rule integer() -> Token
-        = n:$("-"? ['0'..='9']+) { Token::Integer(n.parse().unwrap()) }
+        = n:$("-"? ['0'..='9']+) {? n.parse().map(Token::Integer).or(Err("integer")) }
 
     rule float() -> Token
-        = n:$("-"? ['0'..='9']+ "." ['0'..='9']+) { Token::Float(n.parse().unwrap()) }
+        = n:$("-"? ['0'..='9']+ "." ['0'..='9']+) {? n.parse().map(Token::Float).or(Err("float")) }
 
     rule boolean() -> Token
         = "true" { Token::Bool(true) }
@@ -165,7 +224,12 @@ parser!(pub grammar parser() for str {
         = quiet!{ "[" WHITESPACE() elements:(( WHITESPACE() e:expression() WHITESPACE() {e}) ** ",") WHITESPACE() "]" { Token::Array(elements) } }
 
     rule dictionary() -> Token
-        = "{" WHITESPACE() kv:(( WHITESPACE() k:string() WHITESPACE() ":" WHITESPACE() e:expression() WHITESPACE() {  Token::KeyValuePair(k.to_string(), Box::new(e)) } ) ** ",") WHITESPACE() "}" { Token::Dictionary(kv) }
+        = "{" WHITESPACE() kv:(( WHITESPACE() k:dictionary_key() WHITESPACE() ":" WHITESPACE() e:expression() WHITESPACE() {  Token::KeyValuePair(Box::new(k), Box::new(e)) } ) ** ",") WHITESPACE() "}" { Token::Dictionary(kv) }
+
+    // literal keys or a computed key in square brackets
+    rule dictionary_key() -> Token
+        = string() / float() / integer() / boolean()
+        / "[" WHITESPACE() e:expression() WHITESPACE() "]" { e }
 
     rule array_index() -> Token
         =  i:identifier() "[" WHITESPACE() e:expression() WHITESPACE() "]" { Token::CollectionIndex(Box::new(i), Box::new(e)) }
@@ -174,15 +238,45 @@ parser!(pub grammar parser() for str {
         = "null" { Token::Null }
 
     rule new_object() -> Token
-        = "new" _ i:identifier() _ "(" _ args:arg_list() _ ")" { Token::NewObject(i.to_string(), args) }
+        = "new" _ i:qualified_name() _ "(" _ args:arg_list() _ ")" { Token::NewObject(i, args) }
+
+    // a name that may belong to an imported module such as vector.point
+    rule qualified_name() -> String
+        = n:$(identifier() ("." identifier())?) { n.to_owned() }
 
     //==============================================================================================
     // WHITESPACE
 
-    rule _() =  quiet!{[' ' | '\t']*}
+    // a block comment can be used anywhere spaces can, even in the middle of a line
+    rule _() = (quiet!{[' ' | '\t']} / BLOCK_COMMENT())*
     rule NEWLINE() = quiet!{ ['\n'|'\r'] }
     rule NEWLINES() = quiet!{ ['\n'|'\r']* }
-    rule WHITESPACE() = quiet!{ [' '|'\t'|'\n'|'\r']* }
+    rule WHITESPACE() = (quiet!{[' '|'\t'|'\n'|'\r']} / COMMENT())*
+
+    // comments are whitespace, a block comment can span lines and a line comment runs to the end of the line
+    rule COMMENT() = BLOCK_COMMENT() / quiet!{ "--" !"[[" [^'\n'|'\r']* }
+
+    // a block comment that is never closed is an error instead of a line comment
+    rule BLOCK_COMMENT() = quiet!{ "--[[" (!"]]" [_])* } "]]"
+    rule IDENTCHAR() = quiet!{ ['a'..='z' | 'A'..='Z' | '0'..='9' | '_'] }
     rule UTF8CHAR() -> char = quiet!{ c:([^ '\x00'..='\x1F' | '\t' | '\n'|'\r']) { c } }
 
-});
\ No newline at end of file
+});
+
+// join neighbouring text together, a string with nothing to interpolate stays a plain string
+fn interpolate(parts: Vec<Token>) -> Token {
+
+    let mut joined: Vec<Token> = vec![];
+    for part in parts {
+        match (joined.last_mut(), part) {
+            (Some(Token::String(text)), Token::String(more)) => text.push_str(more.as_str()),
+            (_, part) => joined.push(part)
+        }
+    }
+
+    match joined.as_slice() {
+        [] => Token::String(String::new()),
+        [Token::String(_)] => joined.remove(0),
+        _ => Token::Interpolation(joined)
+    }
+}
\ No newline at end of file
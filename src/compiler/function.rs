@@ -1,65 +1,132 @@
-use std::collections::HashMap;
-use log::error;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use crate::compiler::module::Module;
 use crate::compiler::token::Token;
-use crate::vm::instructions::Instruction;
-use crate::vm::value::Value;
+use crate::error::{OrbError, Span};
+use crate::vm::instructions::{Capture, Instruction};
+use crate::vm::value::{Static, Value};
+
+// the loop being compiled, so break and continue know where to jump
+#[derive(Clone)]
+struct LoopContext {
+
+    // instruction that continue jumps back to
+    start: usize,
+
+    // break placeholders to patch once the end of the loop is known
+    breaks: Vec<usize>,
+
+    // number of iterator values the loop keeps on the stack
+    stack_values: usize,
+}
+
+// every lambda in the program, the index is its unique id
+pub type LambdaTable = Rc<RefCell<Vec<Function>>>;
 
 #[derive(Clone)]
 pub struct Function {
     pub instructions: Vec<Instruction>,
+    pub lines: Vec<(usize, Span)>,
     variables: HashMap<String, usize>,
-    pub anon_functions: HashMap<String, Vec<Instruction>>,
+    loops: Vec<LoopContext>,
+    lambdas: LambdaTable,
+
+    // class that 'super' refers to when compiling a method of a subclass
+    parent_class: Option<String>,
+
+    // names of local variables in the functions this lambda is nested in
+    enclosing: HashSet<String>,
+
+    // variables captured from the enclosing functions, in upvalue slot order
+    upvalues: Vec<String>,
+
     globals: HashMap<String, Value>,
+
+    // the script this function is declared in
+    pub module: Rc<Module>,
+
+    span: Option<Span>,
 }
 
 impl Function {
-    pub fn new(parameters: Vec<Token>, body: Vec<Token>, globals: HashMap<String, Value>) -> Function {
+    pub fn new(parameters: Vec<Token>, body: Vec<Token>, globals: HashMap<String, Value>, module: Rc<Module>, lambdas: LambdaTable, parent_class: Option<String>) -> Result<Function, OrbError> {
+        Function::new_with_enclosing(parameters, body, globals, module, lambdas, parent_class, HashSet::new())
+    }
+
+    // compile a lambda that can capture the given variables of the functions around it
+    fn new_with_enclosing(parameters: Vec<Token>, body: Vec<Token>, globals: HashMap<String, Value>, module: Rc<Module>, lambdas: LambdaTable, parent_class: Option<String>, enclosing: HashSet<String>) -> Result<Function, OrbError> {
 
         let mut f = Function {
             instructions: vec![],
+            lines: vec![],
             variables: Default::default(),
-            anon_functions: Default::default(),
-            globals
+            loops: vec![],
+            lambdas,
+            parent_class,
+            enclosing,
+            upvalues: vec![],
+            globals,
+            module,
+            span: None,
         };
 
         // store the parameters as variables
-        f.add_parameters(parameters.clone());
+        f.add_parameters(parameters)?;
 
         // compile the statements
-        f.compile_statements(body);
+        f.compile_statements(body)?;
 
         // if tha last instruction is not a return then add one
-        if matches!(f.instructions.last(), Some(Instruction::Return(_))) == false {
+        if !matches!(f.instructions.last(), Some(Instruction::Return(_))) {
             f.instructions.push(Instruction::Return(false));
         }
 
-        return f;
+        Ok(f)
     }
 
     //==============================================================================================
     // STATEMENTS
 
     // compile a list of statements
-    fn compile_statements(&mut self, statements: Vec<Token>) {
+    fn compile_statements(&mut self, statements: Vec<Token>) -> Result<(), OrbError> {
         for statement in statements {
-            self.compile_statement(Box::new(statement));
+            self.compile_statement(Box::new(statement))?;
         }
+        Ok(())
     }
 
     // compile a statement
-    fn compile_statement(&mut self, statement: Box<Token>)  {
+    fn compile_statement(&mut self, statement: Box<Token>) -> Result<(), OrbError> {
         match *statement {
+
+            // remember where the statement starts so runtime errors can point at it
+            Token::Located(offset, statement) => {
+                let span = self.module.span(offset);
+                self.span = Some(span.clone());
+                self.lines.push((self.instructions.len(), span));
+                self.compile_statement(statement)
+            },
+
             Token::Variable(name, expr) => self.compile_variable(name, expr),
-            Token::Assign(name, expr) => self.compile_assignment(name, expr),
-            Token::Call(name, args) => self.compile_call(name, args),
+            Token::Assign(name, expr) => self.compile_assignment(*name, expr),
             Token::Return(expr) => self.compile_return(expr),
             Token::WhileLoop(cond, body) => self.compile_while_loop(cond, body),
             Token::ForI(var, start, step, end, body) => self.compile_iterator(var, start, step, end, body),
             Token::ForEach(var, collection, body) =>   self.compile_iterator(var, Box::new(Token::Integer(0)),  Box::new(Token::Integer(1)), collection, body),
-            Token::IfElse(cond, body, else_body) => self.compile_if_else(cond, body, else_body),
-            Token::Comment(_) => { },
-            Token::DotChain(start, chain) => self.compile_chain(start, chain),
-            _ => unimplemented!("statement not implemented: {:?}", statement)
+            Token::ForEachPair(key, value, collection, body) => self.compile_pair_iterator(key, value, collection, body),
+            Token::IfElse(branches, else_body) => self.compile_if_else(branches, else_body),
+            Token::Break => self.compile_break(),
+            Token::Continue => self.compile_continue(),
+
+            // calls used as statements discard their result
+            Token::Call(_, _) | Token::DotChain(_, _) => {
+                self.compile_expression(statement)?;
+                self.instructions.push(Instruction::Pop);
+                Ok(())
+            },
+
+            _ => Err(self.error(format!("statement not implemented: {:?}", statement)))
         }
     }
 
@@ -67,38 +134,62 @@ impl Function {
     //==============================================================================================
     // VARIABLES
 
-    fn add_parameters(&mut self, parameters: Vec<Token>) {
+    fn add_parameters(&mut self, parameters: Vec<Token>) -> Result<(), OrbError> {
         for param in parameters {
-            self.add_variable(param.to_string());
+            self.add_variable(param.to_string())?;
         }
+        Ok(())
     }
 
-    fn compile_variable(&mut self, name: Box<Token>, expr: Box<Token>) {
+    fn compile_variable(&mut self, name: Box<Token>, expr: Box<Token>) -> Result<(), OrbError> {
 
         // Declare variable
-        let slot = self.add_variable(name.to_string());
+        let slot = self.add_variable(name.to_string())?;
+
+        // variables declared inside a loop get a fresh cell each time round,
+        // so lambdas created in earlier iterations keep their own copy
+        if !self.loops.is_empty() {
+            self.instructions.push(Instruction::ResetLocalVariable(slot));
+        }
 
         // compile the value
-        self.compile_expression(expr);
+        self.compile_expression(expr)?;
 
         // store the value
         self.instructions.push(Instruction::MoveToLocalVariable(slot));
 
+        Ok(())
     }
 
     // compile assignment
-    fn compile_assignment(&mut self, left: Box<Token>, right: Box<Token>) {
+    fn compile_assignment(&mut self, left: Token, right: Box<Token>) -> Result<(), OrbError> {
 
-        match *left.clone() {
+        match left {
 
             // store value in variable
             Token::Identifier(name) => {
 
+                // variables captured from an enclosing function are stored through their upvalue
+                if !self.variables.contains_key(name.as_str()) {
+                    if let Some(upvalue) = self.resolve_upvalue(name.as_str()) {
+                        self.compile_expression(right)?;
+                        self.instructions.push(Instruction::StoreUpvalue(upvalue));
+                        return Ok(());
+                    }
+                }
+
+                // variables declared at the top of the module are stored as globals
+                if !self.variables.contains_key(name.as_str()) && self.module.variables.contains(name.as_str()) {
+                    self.compile_expression(right)?;
+                    self.instructions.push(Instruction::StoreGlobal(self.module.qualify(name.as_str())));
+                    return Ok(());
+                }
+
                 // get the variable slot
-                let slot = self.get_variable(name.as_str());
+                let slot = self.get_variable(name.as_str())?;
 
                 // compile the value
-                self.compile_expression(right);
+                self.compile_expression(right)?;
 
                 // store the value
                 self.instructions.push(Instruction::MoveToLocalVariable(slot));
@@ -107,10 +198,12 @@ impl Function {
             Token::DotChain(start, mut chain) => {
 
                 // remove last item from chain
-                let last_item = chain.pop().expect("chain to have at least one item");
+                let Some(last_item) = chain.pop() else {
+                    return Err(self.error(String::from("can not assign to an empty chain")));
+                };
 
-                self.compile_chain(start, chain);
-                self.compile_expression(right);
+                self.compile_chain(start, chain)?;
+                self.compile_expression(right)?;
 
                 match last_item {
                     Token::Identifier(name) => {
@@ -123,7 +216,7 @@ impl Function {
                     //     self.instructions.push(Instruction::PushString(name.to_string()));
                     //     self.instructions.push(Instruction::SetCollectionItem);
                     // },
-                    _ => panic!("last item in chain is not a variable or index")
+                    _ => return Err(self.error(String::from("last item in chain is not a variable or index")))
                 }
 
             },
@@ -131,126 +224,159 @@ impl Function {
             // store value in array index
             Token::CollectionIndex(name, index) => {
 
-                // load the variable
-                let Token::Identifier(name_as_string) = *name else { panic!("name is not an identifier") };
-                let slot = self.get_variable(name_as_string.as_str());
-                self.instructions.push(Instruction::LoadLocalVariable(slot));
+                // load the collection
+                self.compile_expression(name)?;
 
                 // compile the value
-                self.compile_expression(right);
+                self.compile_expression(right)?;
 
                 // compile the index
-                self.compile_expression(index);
+                self.compile_expression(index)?;
 
                 // add value to collection
                 self.instructions.push(Instruction::SetCollectionItem);
-
-                // update variable
-                self.instructions.push(Instruction::MoveToLocalVariable(slot));
             },
 
-            _ => panic!("name is not an identifier or index")
+            _ => return Err(self.error(String::from("can only assign to a variable, member or index")))
         }
 
+        Ok(())
     }
 
     //==============================================================================================
     // FUNCTIONS
 
     // compile a function call
-    fn compile_call(&mut self, name: Box<Token>, args: Vec<Token>) {
+    fn compile_call(&mut self, name: Box<Token>, args: Vec<Token>) -> Result<(), OrbError> {
 
         let arg_len = args.len();
         let function_name = name.to_string();
 
-        if self.variables.contains_key(&function_name) {
-            self.instructions.push(Instruction::LoadLocalVariable(self.get_variable(function_name.as_str())));
+        // super(...) runs the parent constructor on this object
+        if function_name == "super" {
+            let parent = self.get_parent_class()?;
+            self.instructions.push(Instruction::PushFunctionRef(format!("{}.{}", parent, parent)));
+            return self.compile_super_call(args);
+        }
+
+        if let Some(slot) = self.variables.get(&function_name) {
+            self.instructions.push(Instruction::LoadLocalVariable(*slot));
+        } else if let Some(upvalue) = self.resolve_upvalue(function_name.as_str()) {
+            self.instructions.push(Instruction::LoadUpvalue(upvalue));
         } else {
-            self.instructions.push(Instruction::PushFunctionRef(function_name));
+            self.instructions.push(Instruction::PushFunctionRef(self.module.resolve(function_name.as_str(), &self.globals)));
         }
 
         // compile the arguments
         for arg in args {
-            self.compile_expression(Box::new(arg));
+            self.compile_expression(Box::new(arg))?;
         }
 
         self.instructions.push(Instruction::Call(arg_len));
+
+        Ok(())
+    }
+
+    // call a parent class function with 'self' as the first argument
+    fn compile_super_call(&mut self, args: Vec<Token>) -> Result<(), OrbError> {
+
+        let arg_len = args.len() + 1;
+
+        // load self
+        self.compile_expression(Box::new(Token::Identifier(String::from("self"))))?;
+
+        // compile the arguments
+        for arg in args {
+            self.compile_expression(Box::new(arg))?;
+        }
+
+        self.instructions.push(Instruction::Call(arg_len));
+
+        Ok(())
     }
 
     // compile a return statement
-    fn compile_return(&mut self, expr: Box<Token>) {
-        self.compile_expression(expr);
+    fn compile_return(&mut self, expr: Box<Token>) -> Result<(), OrbError> {
+        self.compile_expression(expr)?;
         self.instructions.push(Instruction::Return(true));
+        Ok(())
     }
 
 
     //==============================================================================================
     // CLASSES
 
-    fn compile_new_object(&mut self, class_name: String, params: Vec<Token>) {
+    fn compile_new_object(&mut self, class_name: String, params: Vec<Token>) -> Result<(), OrbError> {
 
         // get params length
         let params_len = params.len();
 
         // load params
         for param in params {
-            self.compile_expression(Box::new(param));
+            self.compile_expression(Box::new(param))?;
         }
 
         // create object
-        self.instructions.push(Instruction::CreateObject(class_name.clone(), params_len));
+        self.instructions.push(Instruction::CreateObject(self.module.resolve(class_name.as_str(), &self.globals), params_len));
 
+        Ok(())
     }
 
     //==============================================================================================
     // IF
 
     // compile if statement
-    fn compile_if_else(&mut self, expr: Box<Token>, then_body: Vec<Token>, else_body: Option<Vec<Token>>) {
+    fn compile_if_else(&mut self, branches: Vec<(Token, Vec<Token>)>, else_body: Option<Vec<Token>>) -> Result<(), OrbError> {
 
-        // Compile If Statement
-        self.compile_expression(expr);
+        let mut jumps_to_end = vec![];
 
-        // Jump to Else if not True
-        let jump_to_else= self.instructions.len();
-        self.instructions.push(Instruction::Halt(String::from("no where to jump to")));
+        for (expr, body) in branches {
 
-        // Compile Statements for True
-        self.compile_statements(then_body);
-        let jump_to_end= self.instructions.len();
-        self.instructions.push(Instruction::Halt(String::from("can not jump tot end")));
+            // Compile If Statement
+            self.compile_expression(Box::new(expr))?;
 
-        // Update Else Jump
-        let jump_to_pos = self.instructions.len() - jump_to_else;
-        self.instructions[jump_to_else] = Instruction::JumpIfFalse(jump_to_pos as i32);
+            // Jump to next branch if not True
+            let jump_to_next = self.instructions.len();
+            self.instructions.push(Instruction::Halt(String::from("no where to jump to")));
 
-        match else_body {
-            None => {}
-            Some(els) => {
-                let _ = self.compile_statements(els);
-            }
+            // Compile Statements for True
+            self.compile_statements(body)?;
+            jumps_to_end.push(self.instructions.len());
+            self.instructions.push(Instruction::Halt(String::from("can not jump tot end")));
+
+            // Update Next Branch Jump
+            let jump_to_pos = self.instructions.len() - jump_to_next;
+            self.instructions[jump_to_next] = Instruction::JumpIfFalse(jump_to_pos as i32);
+        }
+
+        if let Some(els) = else_body {
+            self.compile_statements(els)?;
+        }
+
+        // Update Jumps to End
+        for jump_to_end in jumps_to_end {
+            self.instructions[jump_to_end] = Instruction::JumpForward(self.instructions.len() - jump_to_end);
         }
 
-        // Update Jump to End
-        self.instructions[jump_to_end] = Instruction::JumpForward(self.instructions.len() - jump_to_end);
+        Ok(())
     }
 
     //==============================================================================================
     // LOOPS
 
-    fn compile_iterator(&mut self, var: Box<Token>, counter_start_at: Box<Token>, counter_step: Box<Token>, target: Box<Token>,  block: Vec<Token>) {
+    fn compile_iterator(&mut self, var: Box<Token>, counter_start_at: Box<Token>, counter_step: Box<Token>, target: Box<Token>,  block: Vec<Token>) -> Result<(), OrbError> {
 
         // compile var
-        let var_slot = self.add_variable(var.to_string());
+        let var_slot = self.add_variable(var.to_string())?;
 
         // compile target
-        self.compile_expression(target);
+        self.compile_expression(target)?;
 
         // compile counter step
-        self.compile_expression(counter_step);
+        self.compile_expression(counter_step)?;
 
         // compile counter start
-        self.compile_expression(counter_start_at);
+        self.compile_expression(counter_start_at)?;
 
         // Create Iterator
         self.instructions.push(Instruction::IteratorNew);
@@ -259,8 +385,9 @@ impl Function {
         let start_ins_ptr = self.instructions.len();
         self.instructions.push(Instruction::Halt(String::from("iterator not updated")));
 
-        // compile statements inside loop block
-        self.compile_statements(block);
+        // compile statements inside loop block, the iterator and counter stay on the stack
+        self.enter_loop(start_ins_ptr, 2);
+        self.compile_statements(block)?;
 
         // jump back to start
         self.instructions.push(Instruction::JumpBackward(self.instructions.len() - start_ins_ptr));
@@ -269,23 +396,60 @@ impl Function {
         let jump_to_pos = self.instructions.len() - start_ins_ptr;
         self.instructions[start_ins_ptr] = Instruction::IteratorNext(var_slot, jump_to_pos);
 
+        self.exit_loop();
+
+        Ok(())
+    }
+
+    // compile a loop over the keys and values of a dictionary or object, or the indexes and items of an array
+    fn compile_pair_iterator(&mut self, key: Box<Token>, value: Box<Token>, target: Box<Token>, block: Vec<Token>) -> Result<(), OrbError> {
+
+        // compile vars
+        let key_slot = self.add_variable(key.to_string())?;
+        let value_slot = self.add_variable(value.to_string())?;
+
+        // compile target
+        self.compile_expression(target)?;
+
+        // Create Iterator
+        self.instructions.push(Instruction::PairIteratorNew);
+
+        // temp jump to end
+        let start_ins_ptr = self.instructions.len();
+        self.instructions.push(Instruction::Halt(String::from("iterator not updated")));
+
+        // compile statements inside loop block, the keys, values and counter stay on the stack
+        self.enter_loop(start_ins_ptr, 3);
+        self.compile_statements(block)?;
+
+        // jump back to start
+        self.instructions.push(Instruction::JumpBackward(self.instructions.len() - start_ins_ptr));
+
+        // update iterator
+        let jump_to_pos = self.instructions.len() - start_ins_ptr;
+        self.instructions[start_ins_ptr] = Instruction::PairIteratorNext(key_slot, value_slot, jump_to_pos);
+
+        self.exit_loop();
+
+        Ok(())
     }
 
     // compile while loop
-    fn compile_while_loop(&mut self, expr: Box<Token>, block: Vec<Token>) {
+    fn compile_while_loop(&mut self, expr: Box<Token>, block: Vec<Token>) -> Result<(), OrbError> {
 
         // Mark instruction pointer
         let start_ins_ptr = self.instructions.len();
 
         // Compile expression
-        self.compile_expression(expr);
+        self.compile_expression(expr)?;
 
         // Jump to end if expression is false
         let jump_not_true = self.instructions.len();
         self.instructions.push(Instruction::Halt(String::from("no jump-not-true provided")));
 
         // Compile statements inside loop block
-        self.compile_statements(block);
+        self.enter_loop(start_ins_ptr, 0);
+        self.compile_statements(block)?;
 
         // Goto loop start
         self.instructions.push(Instruction::JumpBackward(self.instructions.len() - start_ins_ptr));
@@ -294,15 +458,116 @@ impl Function {
         let jump_to_pos = self.instructions.len() - jump_not_true;
         self.instructions[jump_not_true] = Instruction::JumpIfFalse(jump_to_pos as i32);
 
+        self.exit_loop();
+
+        Ok(())
+    }
+
+    // start collecting break and continue jumps for a new loop
+    fn enter_loop(&mut self, start: usize, stack_values: usize) {
+        self.loops.push(LoopContext { start, breaks: vec![], stack_values });
+    }
+
+    // point the breaks of the innermost loop at the current instruction
+    fn exit_loop(&mut self) {
+        let context = self.loops.pop().expect("loop context should exist");
+        for jump in context.breaks {
+            self.instructions[jump] = Instruction::JumpForward(self.instructions.len() - jump);
+        }
+    }
+
+    // compile a break statement
+    fn compile_break(&mut self) -> Result<(), OrbError> {
+
+        let Some(context) = self.loops.last() else {
+            return Err(self.error(String::from("break used outside of a loop")));
+        };
+
+        // clear the iterator values from the stack
+        for _ in 0..context.stack_values {
+            self.instructions.push(Instruction::Pop);
+        }
+
+        // jump to the end of the loop once it is known
+        let jump_to_end = self.instructions.len();
+        self.instructions.push(Instruction::Halt(String::from("break not updated")));
+        self.loops.last_mut().expect("loop context should exist").breaks.push(jump_to_end);
+
+        Ok(())
+    }
+
+    // compile a continue statement
+    fn compile_continue(&mut self) -> Result<(), OrbError> {
+
+        let Some(context) = self.loops.last() else {
+            return Err(self.error(String::from("continue used outside of a loop")));
+        };
+
+        // go back to the start of the loop
+        self.instructions.push(Instruction::JumpBackward(self.instructions.len() - context.start));
+
+        Ok(())
     }
 
     //==============================================================================================
     // DOT CHAIN
 
-    fn compile_chain(&mut self, start: Box<Token>, chain: Vec<Token>) {
+    fn compile_chain(&mut self, mut start: Box<Token>, mut chain: Vec<Token>) -> Result<(), OrbError> {
+
+        // names from an imported module start with the name it was imported as
+        if let Some(alias) = self.get_import(&start).filter(|_| !chain.is_empty()) {
+            start = Box::new(match chain.remove(0) {
+                Token::Identifier(name) => Token::Identifier(format!("{}.{}", alias, name)),
+                Token::Call(name, args) => Token::Call(Box::new(Token::Identifier(format!("{}.{}", alias, name))), args),
+                Token::CollectionIndex(name, index) => Token::CollectionIndex(Box::new(Token::Identifier(format!("{}.{}", alias, name))), index),
+                item => return Err(self.error(format!("chain item {:?} is not a variable or index", item)))
+            });
+        }
+
+        // super.method(...) runs the method from the parent class on this object
+        if matches!(start.as_ref(), Token::Identifier(name) if name == "super") {
+
+            if chain.is_empty() {
+                return Err(self.error(String::from("super can only be used to call a method")));
+            }
+
+            let Token::Call(name, args) = chain.remove(0) else {
+                return Err(self.error(String::from("super can only be used to call a method")));
+            };
+
+            // find the method in the parent template, which also holds the methods it inherited
+            let parent = self.get_parent_class()?;
+            let method = match self.globals.get(parent.as_str()) {
+                Some(Value::Class(template, _)) => template.get(name.to_string().as_str()).cloned(),
+                _ => None
+            };
+            let Some(Value::FunctionRef(function)) = method else {
+                return Err(self.error(format!("method '{}' does not exist in class {}", name, parent)));
+            };
+
+            self.instructions.push(Instruction::PushFunctionRef(function));
+            self.compile_super_call(args)?;
+
+        } else if let Some(function) = self.get_static_function(&start, chain.first()) {
+
+            // static functions are called straight away without 'self'
+            let Token::Call(_, args) = chain.remove(0) else {
+                unreachable!("static function call should be a call");
+            };
+
+            self.instructions.push(Instruction::PushFunctionRef(function));
+            let arg_len = args.len();
+            for arg in args {
+                self.compile_expression(Box::new(arg))?;
+            }
+            self.instructions.push(Instruction::Call(arg_len));
+
+        } else {
 
-        // load the start of the chain
-        self.compile_expression(start);
+            // load the start of the chain
+            self.compile_expression(start)?;
+
+        }
 
         // for each item in chain
         for item in chain {
@@ -316,7 +581,7 @@ impl Function {
                 Token::CollectionIndex(var, index) => {
                     self.instructions.push(Instruction::PushString(var.to_string()));
                     self.instructions.push(Instruction::GetCollectionItem);
-                    self.compile_expression(index);
+                    self.compile_expression(index)?;
                     self.instructions.push(Instruction::GetCollectionItem);
                 },
                 Token::Call(name, args) => {
@@ -328,24 +593,25 @@ impl Function {
 
                     // compile the arguments
                     for arg in args {
-                        self.compile_expression(Box::new(arg));
+                        self.compile_expression(Box::new(arg))?;
                     }
 
                     self.instructions.push(Instruction::Call(arg_len));
 
                 },
-                _ => unreachable!("chain item {:?} is not a variable or index", item)
+                _ => return Err(self.error(format!("chain item {:?} is not a variable or index", item)))
             }
 
         }
 
+        Ok(())
     }
 
     //==============================================================================================
     // EXPRESSIONS
 
     // compile expression
-    fn compile_expression(&mut self, token: Box<Token>) {
+    fn compile_expression(&mut self, token: Box<Token>) -> Result<(), OrbError> {
 
         match *token {
 
@@ -369,11 +635,22 @@ impl Function {
                 self.instructions.push(Instruction::PushString(v));
             }
 
+            // each part is turned into a string and joined together
+            Token::Interpolation(parts) => {
+                let part_count = parts.len();
+                for part in parts {
+                    self.compile_expression(Box::new(part))?;
+                }
+                self.instructions.push(Instruction::Concat(part_count));
+            }
+
             Token::Identifier(ident) => {
-                if self.variables.contains_key(ident.as_str()) {
-                    self.instructions.push(Instruction::LoadLocalVariable(self.get_variable(ident.as_str())));
+                if let Some(slot) = self.variables.get(ident.as_str()) {
+                    self.instructions.push(Instruction::LoadLocalVariable(*slot));
+                } else if let Some(upvalue) = self.resolve_upvalue(ident.as_str()) {
+                    self.instructions.push(Instruction::LoadUpvalue(upvalue));
                 } else {
-                    self.instructions.push(Instruction::LoadGlobal(ident));
+                    self.instructions.push(Instruction::LoadGlobal(self.module.resolve(ident.as_str(), &self.globals)));
                 }
             }
 
@@ -383,7 +660,7 @@ impl Function {
 
                 // Compile each element
                 for element in elements {
-                    self.compile_expression(Box::new(element));
+                    self.compile_expression(Box::new(element))?;
                 }
 
                 // collect items into array
@@ -396,11 +673,11 @@ impl Function {
                 let dict_size = pairs.len();
 
                 for pair in pairs {
-                    if let Token::KeyValuePair(k, value) = pair {
-                        self.instructions.push(Instruction::PushString(k));
-                        self.compile_expression(value);
+                    if let Token::KeyValuePair(key, value) = pair {
+                        self.compile_expression(key)?;
+                        self.compile_expression(value)?;
                     } else {
-                        panic!("expected key value pair");
+                        return Err(self.error(String::from("expected key value pair")));
                     }
                 }
 
@@ -411,138 +688,265 @@ impl Function {
 
             Token::AnonFunction(args, body) => {
 
-                // create a new function
-                let func_name = format!("lambda_{}", self.anon_functions.len());
-                let f = Function::new(args, body, self.globals.clone());
+                // everything visible here can be captured by the lambda
+                let mut enclosing = self.enclosing.clone();
+                enclosing.extend(self.variables.keys().cloned());
 
-                self.anon_functions.insert(func_name.clone(), f.instructions);
+                // create a new function, any lambdas inside it are added to the table first
+                let f = Function::new_with_enclosing(args, body, self.globals.clone(), self.module.clone(), self.lambdas.clone(), self.parent_class.clone(), enclosing)?;
 
-                // push globalref onto stack
-                self.instructions.push(Instruction::PushFunctionRef(func_name));
+                // work out where each captured variable comes from in this function
+                let mut captures = vec![];
+                for name in f.upvalues.iter() {
+                    if let Some(slot) = self.variables.get(name) {
+                        captures.push(Capture::Local(*slot));
+                    } else if let Some(upvalue) = self.resolve_upvalue(name) {
+                        captures.push(Capture::Upvalue(upvalue));
+                    } else {
+                        return Err(self.error(format!("variable '{}' does not exist", name)));
+                    }
+                }
+
+                // add the lambda to the program wide table to give it a unique name scripts can not call
+                let func_name = {
+                    let mut lambdas = self.lambdas.borrow_mut();
+                    lambdas.push(f);
+                    format!("<lambda {}>", lambdas.len() - 1)
+                };
+
+                // push the function with its captured variables onto stack
+                if captures.is_empty() {
+                    self.instructions.push(Instruction::PushFunctionRef(func_name));
+                } else {
+                    self.instructions.push(Instruction::MakeClosure(func_name, captures));
+                }
             }
 
             Token::CollectionIndex(id, index) => {
 
                 // load array
-                let Token::Identifier(id_name) = *id else { panic!("expected identifier") };
-                self.instructions.push(Instruction::LoadLocalVariable(self.get_variable(id_name.as_str())));
+                self.compile_expression(id)?;
 
                 // compile index
-                self.compile_expression(index);
+                self.compile_expression(index)?;
 
                 // get array value
                 self.instructions.push(Instruction::GetCollectionItem);
 
             }
 
-            Token::NewObject(class_name, params) => self.compile_new_object(class_name, params),
+            Token::NewObject(class_name, params) => self.compile_new_object(class_name, params)?,
 
             Token::DotChain(start, chain) => {
-                self.compile_chain(start, chain);
+                self.compile_chain(start, chain)?;
             }
 
             Token::Call(name, args) => {
-                self.compile_call(name, args);
+                self.compile_call(name, args)?;
+            }
+
+            // the left value is kept as the result when it decides the outcome
+            Token::And(t1, t2) => {
+                self.compile_expression(t1)?;
+                let jump_to_end = self.instructions.len();
+                self.instructions.push(Instruction::Halt(String::from("no where to jump to")));
+                self.compile_expression(t2)?;
+                self.instructions[jump_to_end] = Instruction::JumpIfFalseOrPop(self.instructions.len() - jump_to_end);
+            }
+
+            Token::Or(t1, t2) => {
+                self.compile_expression(t1)?;
+                let jump_to_end = self.instructions.len();
+                self.instructions.push(Instruction::Halt(String::from("no where to jump to")));
+                self.compile_expression(t2)?;
+                self.instructions[jump_to_end] = Instruction::JumpIfTrueOrPop(self.instructions.len() - jump_to_end);
+            }
+
+            Token::Not(t1) => {
+                self.compile_expression(t1)?;
+                self.instructions.push(Instruction::Not);
             }
 
             Token::Eq(t1, t2) => {
-                self.compile_expression(t1);
-                self.compile_expression(t2);
+                self.compile_expression(t1)?;
+                self.compile_expression(t2)?;
                 self.instructions.push(Instruction::Equal);
             }
 
             Token::Ne(t1, t2) => {
-                self.compile_expression(t1);
-                self.compile_expression(t2);
+                self.compile_expression(t1)?;
+                self.compile_expression(t2)?;
                 self.instructions.push(Instruction::NotEqual);
             }
 
             Token::Add(t1, t2) => {
-                self.compile_expression(t1);
-                self.compile_expression(t2);
+                self.compile_expression(t1)?;
+                self.compile_expression(t2)?;
                 self.instructions.push(Instruction::Add);
             }
 
             Token::Sub(t1, t2) => {
-                self.compile_expression(t1);
-                self.compile_expression(t2);
+                self.compile_expression(t1)?;
+                self.compile_expression(t2)?;
                 self.instructions.push(Instruction::Sub);
             }
 
             Token::Mul(t1, t2) => {
-                self.compile_expression(t1);
-                self.compile_expression(t2);
+                self.compile_expression(t1)?;
+                self.compile_expression(t2)?;
                 self.instructions.push(Instruction::Multiply);
             }
 
             Token::Div(t1, t2) => {
-                self.compile_expression(t1);
-                self.compile_expression(t2);
+                self.compile_expression(t1)?;
+                self.compile_expression(t2)?;
                 self.instructions.push(Instruction::Divide);
             }
 
+            Token::IntDiv(t1, t2) => {
+                self.compile_expression(t1)?;
+                self.compile_expression(t2)?;
+                self.instructions.push(Instruction::IntegerDivide);
+            }
+
+            Token::Mod(t1, t2) => {
+                self.compile_expression(t1)?;
+                self.compile_expression(t2)?;
+                self.instructions.push(Instruction::Modulo);
+            }
+
             Token::Pow(t1, t2) => {
-                self.compile_expression(t1);
-                self.compile_expression(t2);
+                self.compile_expression(t1)?;
+                self.compile_expression(t2)?;
                 self.instructions.push(Instruction::Pow);
             }
 
+            Token::Neg(t1) => {
+                self.compile_expression(t1)?;
+                self.instructions.push(Instruction::Negate);
+            }
+
             Token::Lt(a, b) => {
-                self.compile_expression(a);
-                self.compile_expression(b);
+                self.compile_expression(a)?;
+                self.compile_expression(b)?;
                 self.instructions.push(Instruction::LessThan);
             }
 
             Token::Le(a, b) => {
-                self.compile_expression(a);
-                self.compile_expression(b);
+                self.compile_expression(a)?;
+                self.compile_expression(b)?;
                 self.instructions.push(Instruction::LessThanOrEqual);
             }
 
             Token::Gt(a, b) => {
-                self.compile_expression(a);
-                self.compile_expression(b);
+                self.compile_expression(a)?;
+                self.compile_expression(b)?;
                 self.instructions.push(Instruction::GreaterThan);
             }
 
             Token::Ge(a, b) => {
-                self.compile_expression(a);
-                self.compile_expression(b);
+                self.compile_expression(a)?;
+                self.compile_expression(b)?;
                 self.instructions.push(Instruction::GreaterThanOrEqual);
             }
 
             // handle unreadable token and print what it is
-            _ => panic!("unhandled token: {:?}", token),
+            _ => return Err(self.error(format!("unhandled token: {:?}", token))),
 
         }
+
+        Ok(())
     }
 
     //==============================================================================================
     // HELPER FUNCTIONS
 
+    // compile error at the statement being compiled
+    fn error(&self, message: String) -> OrbError {
+        OrbError::compile(message, self.span.clone())
+    }
+
     // get index of variable or error if it doesn't exist
-    fn get_variable(&self, name: &str) -> usize {
-        if let Some(id) = self.variables.get(name) {
-            *id
-        } else {
-            panic!("variable '{}' does not exist", name);
+    fn get_variable(&self, name: &str) -> Result<usize, OrbError> {
+        match self.variables.get(name) {
+            Some(id) => Ok(*id),
+            None => Err(self.error(format!("variable '{}' does not exist", name)))
+        }
+    }
+
+    // get the function for a call such as rectangle.unit() when it is a static function of a class
+    fn get_static_function(&self, start: &Token, item: Option<&Token>) -> Option<String> {
+
+        let (Token::Identifier(class_name), Some(Token::Call(name, _))) = (start, item) else {
+            return None;
+        };
+
+        // local variables hide classes with the same name
+        if self.variables.contains_key(class_name) || self.enclosing.contains(class_name) {
+            return None;
+        }
+
+        match self.globals.get(&self.module.resolve(class_name, &self.globals)) {
+            Some(Value::Class(_, statics)) => match statics.get(name.to_string().as_str()) {
+                Some(Static::Function(function)) => Some(function.clone()),
+                _ => None
+            },
+            _ => None
+        }
+    }
+
+    // get the name a module was imported as when a chain starts with it
+    fn get_import(&self, start: &Token) -> Option<String> {
+
+        let Token::Identifier(alias) = start else {
+            return None;
+        };
+
+        // local variables hide modules with the same name
+        if self.variables.contains_key(alias) || self.enclosing.contains(alias) || !self.module.imports.contains_key(alias) {
+            return None;
         }
+
+        Some(alias.clone())
+    }
+
+    // get the class 'super' refers to or error if this is not a method of a subclass
+    fn get_parent_class(&self) -> Result<String, OrbError> {
+        match &self.parent_class {
+            Some(parent) => Ok(parent.clone()),
+            None => Err(self.error(String::from("super can only be used in a method of a class that extends another class")))
+        }
+    }
+
+    // get the upvalue slot of a variable from an enclosing function, capturing it on first use
+    fn resolve_upvalue(&mut self, name: &str) -> Option<usize> {
+
+        if let Some(index) = self.upvalues.iter().position(|upvalue| upvalue == name) {
+            return Some(index);
+        }
+
+        if !self.enclosing.contains(name) {
+            return None;
+        }
+
+        self.upvalues.push(name.to_string());
+        Some(self.upvalues.len() - 1)
     }
 
     // add variable and return its index or error if it already exists
-    fn add_variable(&mut self, name: String) -> usize {
+    fn add_variable(&mut self, name: String) -> Result<usize, OrbError> {
 
         // check if variable already exists
         if self.variables.contains_key(name.as_str()) {
-            panic!("variable '{}' already exists", name);
+            return Err(self.error(format!("variable '{}' already exists", name)));
         }
 
         // create variable
         let vid = self.variables.len();
 
         // add variable to list
-        self.variables.insert(name.clone(), vid);
+        self.variables.insert(name, vid);
 
-        vid
+        Ok(vid)
     }
 }
\ No newline at end of file
@@ -1,178 +1,437 @@
-use std::collections::HashMap;
-use std::fmt::format;
-use log::{debug, info, trace};
-use crate::compiler::function::Function;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use indexmap::IndexMap;
+use log::{info, trace};
+use crate::compiler::function::{Function, LambdaTable};
+use crate::compiler::module::Module;
+use crate::compiler::source::SourceMap;
 use crate::compiler::token::Token;
-use crate::vm::instructions::Instruction;
+use crate::error::{OrbError, Span, StackFrame};
 use crate::vm::program::Program;
-use crate::vm::value::Value;
+use crate::vm::value::{Static, Value};
 
 mod frontend;
 mod token;
 mod function;
+mod source;
+mod module;
+
+pub use module::{FileLoader, ModuleLoader};
 
 pub struct Compiler {
 
+    // finds the source of imported modules
+    loader: Box<dyn ModuleLoader>,
+
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
 }
 
 impl Compiler {
 
+    // imported modules are loaded from files relative to the current directory
     pub fn new() -> Compiler {
         Compiler {
-
+            loader: Box::new(FileLoader::new(".")),
         }
     }
 
-    pub fn compile(&mut self, source: &str) -> Result<Program, String> {
+    // load imported modules some other way, such as from memory
+    pub fn set_module_loader(&mut self, loader: impl ModuleLoader + 'static) {
+        self.loader = Box::new(loader);
+    }
+
+    pub fn compile(&mut self, source: &str) -> Result<Program, OrbError> {
 
         info!("Compiling program");
 
         // create a new program
         let mut p = Program::new();
 
-        // Tokenize Code
-        let script: Vec<Token> = frontend::parser::script(source).map_err(|e| e.to_string())?;
+        // the script and every module it imports, imported modules come first
+        let mut modules = vec![];
+        self.load_module(None, source, &mut vec![], &mut modules)?;
 
-        trace!("Tokens: {:?}", script);
+        // lambdas from every function are collected here so their names are unique
+        let lambdas: LambdaTable = Rc::new(RefCell::new(vec![]));
+
+        // the class each class extends, as written in the module the class is in
+        let mut extends = vec![];
 
         // compile globals
-        for token in script.clone() {
-
-            match token {
-                Token::Constant(name, value) => {
-                    match *value {
-                        Token::Integer(i) => { p.globals.insert(name.to_string(), Value::Integer(i)); },
-                        Token::Float(f) => { p.globals.insert(name.to_string(), Value::Float(f)); },
-                        Token::String(s) => { p.globals.insert(name.to_string(), Value::String(s)); },
-                        Token::Bool(b) => { p.globals.insert(name.to_string(), Value::Bool(b)); },
-                        _ => {}
-                    }
-                },
-                Token::Class(name, body) => {
-
-                    // class template
-                    let mut class_def = HashMap::new();
-
-                    // Build template for class
-                    for item in body {
-                        match item {
-                            Token::Function(_, name, args, body) => { class_def.insert(name.to_string(), Value::FunctionRef(format!("{}.{}", name, name))); },
-                            Token::Variable(name, value) => { class_def.insert(name.to_string(), Value::Null); }
-                            _ => unreachable!("Invalid class body item")
+        for (module, script) in modules.iter() {
+
+            // variables are null until the top level statements set them
+            for name in module.variables.iter() {
+                p.globals.insert(module.qualify(name.as_str()), Value::Null);
+            }
+
+            for token in script.clone() {
+
+                match token {
+                    Token::Constant(name, value) => {
+                        if let Some(value) = Compiler::constant_value(*value) {
+                            p.globals.insert(module.qualify(name.to_string().as_str()), value);
+                        }
+                    },
+                    Token::Class(class_name, parent, body) => {
+
+                        if let Some(parent) = parent {
+                            extends.push((module.qualify(class_name.as_str()), parent, module.clone()));
+                        }
+
+                        // class template
+                        let mut class_def = IndexMap::new();
+                        let mut statics = IndexMap::new();
+
+                        // Build template for class
+                        for item in body {
+                            let Token::Located(_, item) = item else {
+                                return Err(OrbError::compile(format!("invalid item in class {}", class_name), None));
+                            };
+                            match *item {
+                                Token::Function(_, name, _, _) => { class_def.insert(name.to_string(), Value::FunctionRef(module.method_name(class_name.as_str(), name.as_str()))); },
+                                Token::Variable(name, _) => { class_def.insert(name.to_string(), Value::Null); }
+                                Token::Static(item) => match *item {
+                                    Token::Function(_, name, _, _) => { statics.insert(name.to_string(), Static::Function(module.method_name(class_name.as_str(), name.as_str()))); },
+                                    Token::Variable(name, _) => { statics.insert(name.to_string(), Static::Variable(module.qualify(format!("{}.{}", class_name, name).as_str()))); },
+                                    _ => return Err(OrbError::compile(format!("invalid static item in class {}", class_name), None))
+                                },
+                                _ => return Err(OrbError::compile(format!("invalid item in class {}", class_name), None))
+                            }
                         }
-                    }
 
-                    // add default constructor
-                    class_def.insert(name.clone(), Value::Null);
+                        // add the class to the global scope
+                        p.globals.insert(module.qualify(class_name.as_str()), Value::Class(class_def, statics));
 
-                    // add the class to the global scope
-                    p.globals.insert(name.to_string(), Value::Class(class_def));
+                    },
+                    Token::Function(_, name, _, _) =>  { p.globals.insert(module.qualify(name.as_str()), Value::FunctionPointer(0)); },
+                    _ => {},
+                }
 
-                },
-                Token::Function(parent_class, name, args, body) =>  { p.globals.insert(name.to_string(), Value::FunctionPointer(0)); },
-                _ => {},
             }
+        }
 
+        // parent classes can be found once every class is known
+        let parents: HashMap<String, String> = extends.into_iter()
+            .map(|(class_name, parent, module)| (class_name, module.resolve(parent.as_str(), &p.globals)))
+            .collect();
+
+        // copy inherited fields and methods into each class template
+        for class_name in parents.keys() {
+            Compiler::inherit(&mut p, &parents, class_name, &mut vec![])?;
         }
 
         // compile functions and update globals
-        for token in script.clone() {
+        for (module, script) in modules.iter() {
 
-            match token {
-                Token::Class(class_name, body) => {
+            // statements that set static variables, they run before the top level statements
+            let mut statics = vec![];
 
-                    let mut class_def = HashMap::new();
+            for token in script.clone() {
 
-                    for item in body {
-                        match item {
+                match token {
+                    Token::Class(class_name, _, body) => {
 
-                            // add the function instruction pointer to the class
-                            Token::Function(_, name, mut args, body) => {
+                        let parent = parents.get(&module.qualify(class_name.as_str())).cloned();
 
-                                // push 'self' into the arguments
-                                args.insert(0, Token::String("self".to_string()));
+                        // statements that set the fields of a new object, starting with the parent fields
+                        let mut initializer = vec![];
+                        if let Some(parent) = &parent {
+                            initializer.push(Token::Call(Box::new(Token::Identifier(format!("{}.<init>", parent))), vec![Token::Identifier(String::from("self"))]));
+                        }
 
-                                // create a new function
-                                let func = Function::new(args.clone(), body.clone(), p.globals.clone());
+                        let mut has_constructor = false;
 
-                                // function name with class
-                                let full_class_function_name = format!("{}.{}", class_name, name);
+                        for item in body {
 
-                                // get the position of the function
-                                class_def.insert(name.to_string(), Value::FunctionRef(full_class_function_name.clone()));
+                            // members keep where they start so errors in their initializers can point at them
+                            let Token::Located(offset, item) = item else {
+                                return Err(OrbError::compile(format!("invalid item in class {}", class_name), None));
+                            };
 
-                                p.globals.insert(full_class_function_name, Value::FunctionPointer(p.instructions.len()));
+                            match *item {
 
-                                // add the function to the program
-                                p.instructions.extend(func.instructions);
+                                // add the function instruction pointer to the class
+                                Token::Function(_, name, mut args, body) => {
 
-                                // loop through the anonymous functions
-                                for (name, instructions) in func.anon_functions.iter() {
-                                    p.globals.insert(name.to_string(), Value::FunctionPointer(p.instructions.len()));
-                                    p.instructions.extend(instructions.clone());
-                                }
+                                    has_constructor |= name == class_name;
+
+                                    // push 'self' into the arguments
+                                    args.insert(0, Token::String("self".to_string()));
+
+                                    // create a new function
+                                    let func = Function::new(args, body, p.globals.clone(), module.clone(), lambdas.clone(), parent.clone())?;
+
+                                    // function name with class
+                                    let full_class_function_name = module.method_name(class_name.as_str(), name.as_str());
 
-                            },
+                                    // add the function to the program
+                                    let function_position = Compiler::link(&mut p, func, Some(class_name.as_str()), name.as_str());
+                                    p.globals.insert(full_class_function_name, Value::FunctionPointer(function_position));
 
-                            // add the variable to the class
-                            Token::Variable(name, value) => {
-                                class_def.insert(name.to_string(), Value::Null);
+                                },
+
+                                // fields with a value are set on each new object
+                                Token::Variable(name, value) => {
+                                    if !matches!(*value, Token::Null) {
+                                        let field = Token::DotChain(Box::new(Token::Identifier(String::from("self"))), vec![*name]);
+                                        initializer.push(Token::Located(offset, Box::new(Token::Assign(Box::new(field), value))));
+                                    }
+                                }
+
+                                // static functions are called without 'self'
+                                Token::Static(item) => match *item {
+                                    Token::Function(_, name, args, body) => {
+                                        let func = Function::new(args, body, p.globals.clone(), module.clone(), lambdas.clone(), None)?;
+                                        let function_position = Compiler::link(&mut p, func, Some(class_name.as_str()), name.as_str());
+                                        p.globals.insert(module.method_name(class_name.as_str(), name.as_str()), Value::FunctionPointer(function_position));
+                                    },
+                                    Token::Variable(name, value) => {
+                                        if !matches!(*value, Token::Null) {
+                                            let variable = Token::DotChain(Box::new(Token::Identifier(class_name.clone())), vec![*name]);
+                                            statics.push(Token::Located(offset, Box::new(Token::Assign(Box::new(variable), value))));
+                                        }
+                                    },
+                                    _ => return Err(OrbError::compile(format!("invalid static item in class {}", class_name), None))
+                                },
+
+                                _ => return Err(OrbError::compile(format!("invalid item in class {}", class_name), None))
                             }
+                        }
+
+                        // compile the field initializer which runs before the constructor
+                        let func = Function::new(vec![Token::String("self".to_string())], initializer, p.globals.clone(), module.clone(), lambdas.clone(), parent.clone())?;
+                        let function_position = Compiler::link(&mut p, func, Some(class_name.as_str()), "<init>");
+                        p.globals.insert(format!("{}.<init>", module.qualify(class_name.as_str())), Value::FunctionPointer(function_position));
 
-                            _ => unreachable!("Invalid class body item")
+                        // classes without a constructor and without a parent to take one from get an empty one
+                        if !has_constructor && parent.is_none() {
+                            let func = Function::new(vec![Token::String("self".to_string())], vec![], p.globals.clone(), module.clone(), lambdas.clone(), None)?;
+                            let function_position = Compiler::link(&mut p, func, Some(class_name.as_str()), class_name.as_str());
+                            p.globals.insert(module.method_name(class_name.as_str(), class_name.as_str()), Value::FunctionPointer(function_position));
                         }
+
                     }
+                    Token::Function(parent_class, name, args, body) => {
 
-                    p.globals.insert(class_name.to_string(), Value::Class(class_def));
+                        // create a new function
+                        let func = Function::new(args, body, p.globals.clone(), module.clone(), lambdas.clone(), None)?;
 
-                }
-                Token::Function(parent_class, name, args, body) => {
+                        // add the function to the program and get its position
+                        let function_instruction_pointer = Value::FunctionPointer(Compiler::link(&mut p, func, parent_class.as_deref(), name.as_str()));
 
-                    // create a new function
-                    let func = Function::new(args.clone(), body.clone(), p.globals.clone());
+                        // if the function is attached to a class
+                        if let Some(class_name) = parent_class {
 
-                    // get the position of the function
-                    let function_instruction_pointer = Value::FunctionPointer(p.instructions.len());
+                            let class_name = module.qualify(class_name.as_str());
 
-                    // add the function to the program
-                    p.instructions.extend(func.instructions);
+                            // if the class does not exist, create it
+                            if !p.globals.contains_key(class_name.as_str()) {
+                                p.globals.insert(class_name.to_string(), Value::Class(IndexMap::new(), Default::default()));
+                            }
 
-                    // loop through the anonymous functions
-                    for (name, instructions) in func.anon_functions.iter() {
-                        p.globals.insert(name.to_string(), Value::FunctionPointer(p.instructions.len()));
-                        p.instructions.extend(instructions.clone());
-                    }
+                            // get global class and add new entry to it
+                            if let Some(Value::Class(class, _)) = p.globals.get_mut(class_name.as_str()) {
+                                class.insert(name.to_string(), function_instruction_pointer);
+                            }
 
-                    // if the function is attached to a class
-                    if let Some(class_name) = parent_class {
 
-                        // if the class does not exist, create it
-                        if !p.globals.contains_key(class_name.as_str()) {
-                            p.globals.insert(class_name.to_string(), Value::Class(HashMap::new()));
-                        }
+                        } else {
+
+                            // add the function to the global lookup
+                            p.globals.insert(module.qualify(name.as_str()), function_instruction_pointer);
 
-                        // get global class and add new entry to it
-                        if let Value::Class(class) = p.globals.get(class_name.as_str()).expect("Class should exist") {
-                            let mut cc = class.clone();
-                            cc.insert(name.to_string(), function_instruction_pointer);
-                            p.globals.insert(class_name.to_string(), Value::Class(cc));
                         }
 
+                    },
+                    _ => {},
+                }
 
-                    } else {
+            }
 
-                        // add the function to the global lookup
-                        p.globals.insert(name.to_string(), function_instruction_pointer);
+            // top level statements run once before the entrypoint, the variables they declare are globals
+            let statements: Vec<Token> = statics.into_iter().chain(script.iter().filter_map(|token| match token {
+                Token::Located(offset, statement) => Some(match statement.as_ref() {
+                    Token::Variable(name, value) => Token::Located(*offset, Box::new(Token::Assign(name.clone(), value.clone()))),
+                    _ => token.clone()
+                }),
+                _ => None
+            })).collect();
+
+            if !statements.is_empty() {
+                let func = Function::new(vec![], statements, p.globals.clone(), module.clone(), lambdas.clone(), None)?;
+                let initializer = module.qualify("<script>");
+                let function_position = Compiler::link(&mut p, func, None, "<script>");
+                p.globals.insert(initializer.clone(), Value::FunctionPointer(function_position));
+                p.initializers.push(initializer);
+            }
+        }
 
-                    }
+        // classes without a constructor use the constructor of their parent
+        for class_name in parents.keys() {
+            let constructor = format!("{}.{}", class_name, class_name);
+            if !p.globals.contains_key(&constructor) {
+                if let Some(pointer) = Compiler::inherited_constructor(&p, &parents, class_name) {
+                    p.globals.insert(constructor, pointer);
+                }
+            }
+        }
+
+        // add the lambdas to the program
+        for (id, lambda) in lambdas.take().into_iter().enumerate() {
+            let name = format!("<lambda {}>", id);
+            let lambda_position = Compiler::link(&mut p, lambda, None, name.as_str());
+            p.globals.insert(name, Value::FunctionPointer(lambda_position));
+        }
+
+        Ok(p)
+    }
+
+    // parse a script and every module it imports, each module is added after the modules it imports
+    fn load_module(&mut self, name: Option<String>, source: &str, importing: &mut Vec<String>, modules: &mut Vec<(Rc<Module>, Vec<Token>)>) -> Result<(), OrbError> {
+
+        // Tokenize Code
+        let script: Vec<Token> = frontend::parser::script(source).map_err(|e| {
+            let message = match &name {
+                Some(module) => format!("expected {} in module {:?}", e.expected, module),
+                None => format!("expected {}", e.expected)
+            };
+            OrbError::compile(message, Some(Span { line: e.location.line, column: e.location.column, module: name.clone() }))
+        })?;
+
+        trace!("Tokens: {:?}", script);
+
+        let mut imports = HashMap::new();
 
-                },
-                _ => {},
+        for token in script.iter() {
+
+            let Token::Import(path, alias) = token else {
+                continue;
+            };
+
+            // a module can not import a module that is still being imported
+            if importing.contains(path) {
+                return Err(OrbError::compile(format!("cyclic import: {} -> {}", importing.join(" -> "), path), None));
+            }
+
+            // each module is only loaded once however many modules import it
+            if !modules.iter().any(|(module, _)| module.name.as_ref() == Some(path)) {
+                let module_source = self.loader.load(path).map_err(|e| OrbError::compile(format!("can not import {:?}: {}", path, e), None))?;
+                importing.push(path.clone());
+                self.load_module(Some(path.clone()), module_source.as_str(), importing, modules)?;
+                importing.pop();
+            }
+
+            // modules are used by the last part of their path unless they are imported as something else
+            let alias = alias.clone().unwrap_or_else(|| path.rsplit('/').next().unwrap_or(path).to_string());
+            imports.insert(alias, path.clone());
+        }
+
+        // used to turn parser positions into lines and columns
+        let source = SourceMap::new(source);
+
+        // variables declared at the top of the module
+        let mut variables = HashSet::new();
+        for token in script.iter() {
+            if let Token::Located(offset, statement) = token {
+                if let Token::Variable(variable, _) = statement.as_ref() {
+                    if !variables.insert(variable.to_string()) {
+                        return Err(OrbError::compile(format!("variable '{}' already exists", variable), Some(Span { module: name.clone(), ..source.span(*offset) })));
+                    }
+                }
             }
+        }
 
+        modules.push((Rc::new(Module { name, source, imports, variables }), script));
+
+        Ok(())
+    }
+
+    // add the fields and methods a class inherits to its template, parents first
+    fn inherit(p: &mut Program, parents: &HashMap<String, String>, class_name: &String, visiting: &mut Vec<String>) -> Result<(), OrbError> {
+
+        let Some(parent) = parents.get(class_name) else {
+            return Ok(());
+        };
+
+        if visiting.contains(class_name) {
+            return Err(OrbError::compile(format!("class {} inherits from itself", class_name), None));
+        }
+
+        // make sure the parent has everything from its own parents
+        visiting.push(class_name.clone());
+        Compiler::inherit(p, parents, parent, visiting)?;
+        visiting.pop();
+
+        let Some(Value::Class(parent_def, parent_statics)) = p.globals.get(parent).cloned() else {
+            return Err(OrbError::compile(format!("class {} extends unknown class {}", class_name, parent), None));
+        };
+
+        let Some(Value::Class(class_def, statics)) = p.globals.get_mut(class_name) else {
+            return Err(OrbError::compile(format!("class {} does not exist", class_name), None));
+        };
+
+        // parent members come first, anything the class defines itself overrides the parent
+        let mut merged = parent_def;
+        merged.extend(class_def.drain(..));
+        *class_def = merged;
+
+        // static functions and variables are inherited, a subclass shares the variables of its parent
+        for (name, value) in parent_statics {
+            statics.entry(name).or_insert(value);
+        }
+
+        Ok(())
+    }
+
+    // value of a literal used for a constant or static variable
+    fn constant_value(token: Token) -> Option<Value> {
+        match token {
+            Token::Null => Some(Value::Null),
+            Token::Integer(i) => Some(Value::Integer(i)),
+            Token::Float(f) => Some(Value::Float(f)),
+            Token::String(s) => Some(Value::String(s)),
+            Token::Bool(b) => Some(Value::Bool(b)),
+            _ => None
+        }
+    }
+
+    // find the nearest constructor up the class hierarchy
+    fn inherited_constructor(p: &Program, parents: &HashMap<String, String>, class_name: &String) -> Option<Value> {
+        let mut class_name = class_name;
+        while let Some(parent) = parents.get(class_name) {
+            if let Some(pointer) = p.globals.get(&format!("{}.{}", parent, parent)) {
+                return Some(pointer.clone());
+            }
+            class_name = parent;
         }
+        None
+    }
+
+    // append a compiled function to the program and return where it starts
+    fn link(p: &mut Program, func: Function, class: Option<&str>, name: &str) -> usize {
+
+        let function_position = p.instructions.len();
+
+        // remember which function this is so stack traces can name it
+        p.functions.push((function_position, StackFrame {
+            function: name.to_string(),
+            class: class.map(str::to_string),
+            module: func.module.name.clone(),
+            span: None,
+        }));
+
+        // add the function to the program along with its line table
+        p.lines.extend(func.lines.into_iter().map(|(ip, span)| (function_position + ip, span)));
+        p.instructions.extend(func.instructions);
 
-        return Ok(p);
+        function_position
     }
 
 }
\ No newline at end of file
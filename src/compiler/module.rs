@@ -0,0 +1,103 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use crate::compiler::source::SourceMap;
+use crate::error::Span;
+use crate::vm::value::Value;
+
+// finds the source of a module from the path used to import it
+pub trait ModuleLoader {
+    fn load(&mut self, path: &str) -> Result<String, String>;
+}
+
+// loads "math/vector" from the file math/vector.orb under a root directory
+pub struct FileLoader {
+    root: PathBuf,
+}
+
+impl FileLoader {
+
+    pub fn new(root: impl Into<PathBuf>) -> FileLoader {
+        FileLoader {
+            root: root.into(),
+        }
+    }
+
+}
+
+impl ModuleLoader for FileLoader {
+    fn load(&mut self, path: &str) -> Result<String, String> {
+        let file = self.root.join(format!("{}.orb", path));
+        fs::read_to_string(&file).map_err(|e| format!("can not read {}: {}", file.display(), e))
+    }
+}
+
+// modules held in memory by the host, keyed by import path
+impl ModuleLoader for HashMap<String, String> {
+    fn load(&mut self, path: &str) -> Result<String, String> {
+        self.get(path).cloned().ok_or_else(|| String::from("module does not exist"))
+    }
+}
+
+// a script being compiled, either the main script or a module it imports
+pub struct Module {
+
+    // import path of the module, the globals it declares are prefixed with it
+    pub name: Option<String>,
+
+    pub source: SourceMap,
+
+    // import path of each module imported by this one, keyed by the name it is imported as
+    pub imports: HashMap<String, String>,
+
+    // variables declared at the top of the module
+    pub variables: HashSet<String>,
+}
+
+impl Module {
+
+    // global name of something declared in this module
+    pub fn qualify(&self, name: &str) -> String {
+        match &self.name {
+            Some(module) => format!("{}.{}", module, name),
+            None => name.to_string()
+        }
+    }
+
+    // global name of a method, constructors are named after the full class name twice
+    pub fn method_name(&self, class_name: &str, name: &str) -> String {
+        let class = self.qualify(class_name);
+        if name == class_name {
+            format!("{}.{}", class, class)
+        } else {
+            format!("{}.{}", class, name)
+        }
+    }
+
+    // line and column of a parser position, along with the module it is in
+    pub fn span(&self, offset: usize) -> Span {
+        Span {
+            module: self.name.clone(),
+            ..self.source.span(offset)
+        }
+    }
+
+    // global name a name used in this module refers to, its own declarations come first,
+    // then names from imported modules written as alias.name, then everything else as it is
+    pub fn resolve(&self, name: &str, globals: &HashMap<String, Value>) -> String {
+
+        let qualified = self.qualify(name);
+        if globals.contains_key(&qualified) {
+            return qualified;
+        }
+
+        if let Some((alias, item)) = name.split_once('.') {
+            if let Some(module) = self.imports.get(alias) {
+                return format!("{}.{}", module, item);
+            }
+        }
+
+        name.to_string()
+    }
+
+}
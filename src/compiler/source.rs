@@ -0,0 +1,47 @@
+use crate::error::Span;
+
+// maps byte offsets recorded by the parser back to lines and columns
+#[derive(Debug)]
+pub struct SourceMap {
+    source: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceMap {
+
+    pub fn new(source: &str) -> SourceMap {
+
+        // every line starts after a newline, except the first
+        let mut line_starts = vec![0];
+        line_starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+
+        SourceMap {
+            source: source.to_string(),
+            line_starts,
+        }
+    }
+
+    // line and column (both starting at 1) of a byte offset
+    pub fn span(&self, offset: usize) -> Span {
+        let line = self.line_starts.partition_point(|start| *start <= offset);
+        let line_start = self.line_starts[line - 1];
+        let column = self.source[line_start..offset].chars().count() + 1;
+        Span { line, column, module: None }
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::source::SourceMap;
+    use crate::error::Span;
+
+    #[test]
+    fn test_span() {
+        let map = SourceMap::new("function main()\n    var a = 1\nend");
+        assert_eq!(map.span(0), Span { line: 1, column: 1, module: None });
+        assert_eq!(map.span(20), Span { line: 2, column: 5, module: None });
+        assert_eq!(map.span(30), Span { line: 3, column: 1, module: None });
+    }
+
+}
@@ -1,13 +1,19 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
 #[derive(Debug, Clone)]
 pub enum Token {
 
-    Comment(String),
-    Import(String),
+    // a statement with the byte offset where it starts in the source
+    Located(usize, Box<Token>),
+
+    // module path and the name it is imported as
+    Import(String, Option<String>),
 
     Function(Option<String>, String, Vec<Token>, Vec<Token>),
     AnonFunction(Vec<Token>, Vec<Token>),
-    Class(String, Vec<Token>),
-    Constructor(Vec<Token>, Vec<Token>),
+    Class(String, Option<String>, Vec<Token>),
+    Static(Box<Token>),
     Identifier(String),
 
     DotChain(Box<Token>, Vec<Token>),
@@ -22,14 +28,19 @@ pub enum Token {
     Float(f32),
     Bool(bool),
     String(String),
+    Interpolation(Vec<Token>),
     Array(Vec<Token>),
 
 
     Dictionary(Vec<Token>),
-    KeyValuePair(String, Box<Token>),
+    KeyValuePair(Box<Token>, Box<Token>),
 
     CollectionIndex(Box<Token>, Box<Token>),
 
+    And(Box<Token>, Box<Token>),
+    Or(Box<Token>, Box<Token>),
+    Not(Box<Token>),
+
     Eq(Box<Token>, Box<Token>),
     Ne(Box<Token>, Box<Token>),
     Lt(Box<Token>, Box<Token>),
@@ -40,29 +51,35 @@ pub enum Token {
     Sub(Box<Token>, Box<Token>),
     Mul(Box<Token>, Box<Token>),
     Div(Box<Token>, Box<Token>),
+    IntDiv(Box<Token>, Box<Token>),
+    Mod(Box<Token>, Box<Token>),
     Pow(Box<Token>, Box<Token>),
+    Neg(Box<Token>),
 
-    IfElse(Box<Token>, Vec<Token>, Option<Vec<Token>>),
+    IfElse(Vec<(Token, Vec<Token>)>, Option<Vec<Token>>),
     WhileLoop(Box<Token>, Vec<Token>),
     ForEach(Box<Token>, Box<Token>, Vec<Token>),
+    ForEachPair(Box<Token>, Box<Token>, Box<Token>, Vec<Token>),
     ForI(Box<Token>, Box<Token>, Box<Token>, Box<Token>, Vec<Token>),
+    Break,
+    Continue,
 
     Call(Box<Token>, Vec<Token>),
     Return(Box<Token>)
 }
 
-impl ToString for Token {
-    fn to_string(&self) -> String {
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
             Token::Function(class, name, _, _) => {
                 match class {
-                    Some(class) => format!("{}::{}", class, name),
-                    None => name.to_string()
+                    Some(class) => write!(f, "{}::{}", class, name),
+                    None => write!(f, "{}", name)
                 }
             },
-            Token::Identifier(name) => name.to_string(),
-            Token::String(s) => s.to_string(),
-            _ => String::from("")
+            Token::Identifier(name) => write!(f, "{}", name),
+            Token::String(s) => write!(f, "{}", s),
+            _ => Ok(())
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,143 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+// a position in the script source, or in the module it imports with this path
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub module: Option<String>,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if let Some(module) = &self.module {
+            write!(f, "{}:", module)?;
+        }
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// one function call on the script call stack
+#[derive(Clone, Debug, PartialEq)]
+pub struct StackFrame {
+    pub function: String,
+    pub class: Option<String>,
+
+    // import path of the module the function is declared in, none for the main script
+    pub module: Option<String>,
+
+    pub span: Option<Span>,
+}
+
+impl Display for StackFrame {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.class {
+            Some(class) => write!(f, "in method {}.{}", class, self.function)?,
+            None => write!(f, "in function {}", self.function)?,
+        }
+
+        if let Some(module) = &self.module {
+            write!(f, " in {}", module)?;
+        }
+
+        if let Some(span) = &self.span {
+            write!(f, " at line {}", span.line)?;
+        }
+
+        Ok(())
+    }
+}
+
+// everything that can go wrong while compiling or running a script
+#[derive(Clone, Debug, PartialEq)]
+pub enum OrbError {
+
+    // the script could not be parsed or compiled
+    CompileError { message: String, span: Option<Span> },
+
+    // the script failed while running
+    RuntimeError { message: String, span: Option<Span>, trace: Vec<StackFrame> },
+
+    // a builtin function registered by the host returned an error
+    HostError { function: String, message: String, span: Option<Span>, trace: Vec<StackFrame> },
+
+}
+
+impl OrbError {
+
+    pub fn compile(message: String, span: Option<Span>) -> OrbError {
+        OrbError::CompileError { message, span }
+    }
+
+    pub fn runtime(message: String) -> OrbError {
+        OrbError::RuntimeError { message, span: None, trace: vec![] }
+    }
+
+    pub fn host(function: String, message: String) -> OrbError {
+        OrbError::HostError { function, message, span: None, trace: vec![] }
+    }
+
+    // set the location and call stack of a runtime or host error that does not have one yet
+    pub(crate) fn with_location(self, location: Option<Span>, stack: Vec<StackFrame>) -> OrbError {
+        match self {
+            OrbError::RuntimeError { message, span: None, .. } => OrbError::RuntimeError { message, span: location, trace: stack },
+            OrbError::HostError { function, message, span: None, .. } => OrbError::HostError { function, message, span: location, trace: stack },
+            error => error
+        }
+    }
+
+    // the error message without any location information
+    pub fn message(&self) -> &str {
+        match self {
+            OrbError::CompileError { message, .. } => message,
+            OrbError::RuntimeError { message, .. } => message,
+            OrbError::HostError { message, .. } => message,
+        }
+    }
+
+    // the script call stack when the error happened, innermost call first
+    pub fn trace(&self) -> &[StackFrame] {
+        match self {
+            OrbError::CompileError { .. } => &[],
+            OrbError::RuntimeError { trace, .. } => trace,
+            OrbError::HostError { trace, .. } => trace,
+        }
+    }
+
+    // where in the script the error happened, if known
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            OrbError::CompileError { span, .. } => span.clone(),
+            OrbError::RuntimeError { span, .. } => span.clone(),
+            OrbError::HostError { span, .. } => span.clone(),
+        }
+    }
+
+}
+
+impl Display for OrbError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            OrbError::CompileError { message, .. } => write!(f, "compile error: {}", message)?,
+            OrbError::RuntimeError { message, .. } => write!(f, "runtime error: {}", message)?,
+            OrbError::HostError { function, message, .. } => write!(f, "error in builtin function {}: {}", function, message)?,
+        }
+
+        if let Some(span) = self.span() {
+            write!(f, " at {}", span)?;
+        }
+
+        if !self.trace().is_empty() {
+            write!(f, "\nstack traceback:")?;
+            for frame in self.trace() {
+                write!(f, "\n    {}", frame)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for OrbError {}
@@ -1,13 +1,22 @@
-use log::{debug, info, LevelFilter};
+use log::LevelFilter;
 use simplelog::{ColorChoice, Config, TerminalMode, TermLogger};
-use crate::compiler::Compiler;
-use crate::vm::value::Value;
-use crate::vm::VM;
 
+pub use crate::compiler::{Compiler, FileLoader, ModuleLoader};
+pub use crate::error::{OrbError, Span, StackFrame};
+pub use crate::vm::program::Program;
+pub use crate::vm::value::{Key, Value};
+pub use crate::vm::VM;
+
+// dictionaries keep their entries in insertion order, hosts need the map type to build and read them
+pub use indexmap::IndexMap;
+
+// only the types above are part of the embedding api
 mod compiler;
+mod error;
 mod vm;
 
-pub fn run(program: &str, parameters: Option<Vec<Value>>, entry: Option<String>) -> Result<Option<Value>, String> {
+// compile and run a script in one go with the default builtin functions
+pub fn run(program: &str, parameters: Option<Vec<Value>>, entry: Option<String>) -> Result<Option<Value>, OrbError> {
 
     let _ = TermLogger::init(LevelFilter::Off, Config::default(),TerminalMode::Mixed, ColorChoice::Auto);
 
@@ -18,36 +27,36 @@ pub fn run(program: &str, parameters: Option<Vec<Value>>, entry: Option<String>)
 
     // add callback to vm that prints helloworld
     vm.add_builtin_function("print", |values| {
-        let v = values.get(0).expect("No value to print");
+        let v = values.first().ok_or("No value to print")?;
         println!("{:?}", v.to_string());
-        None
+        Ok(None)
     });
 
     vm.add_builtin_function("assertTrue", |mut values| {
 
-        let msg = values.pop().expect("No msg provided");
-        let bool = values.pop().expect("No boolean");
+        let msg = values.pop().ok_or("No msg provided")?;
+        let bool = values.pop().ok_or("No boolean")?;
 
         if bool != Value::Bool(true) {
-            panic!("Assertion failed: {}", msg.to_string());
+            return Err(format!("Assertion failed: {}", msg));
         }
 
-        None
+        Ok(None)
     });
 
     vm.add_builtin_function("assertEquals", |mut values| {
 
-        let msg = values.pop().expect("No msg provided");
-        let v2 = values.pop().expect("No value provided");
-        let v1 = values.pop().expect("No value provide");
+        let msg = values.pop().ok_or("No msg provided")?;
+        let v2 = values.pop().ok_or("No value provided")?;
+        let v1 = values.pop().ok_or("No value provide")?;
 
         if v1 != v2 {
-            panic!("Assertion failed: {}. Got {} but wanted {}", msg.to_string(), v1.to_string(), v2.to_string());
+            return Err(format!("Assertion failed: {}. Got {} but wanted {}", msg, v1, v2));
         }
 
-        None
+        Ok(None)
     });
 
-    vm.execute(p, parameters, entry)
+    vm.execute(&p, parameters, entry)
 
 }
@@ -1,27 +1,75 @@
+use std::cell::RefCell;
+use std::rc::Rc;
 use crate::vm::value::Value;
 
 #[derive(Debug)]
 pub struct Frame {
+    pub function: String,
     pub return_position: Option<usize>,
-    pub variables: Vec<Value>,
+    pub is_constructor: bool,
+
+    // initializers set up the fields of a new object and return nothing
+    pub is_initializer: bool,
+
+    // called from inside an instruction, returning hands the result back to the vm
+    pub is_callback: bool,
+
+    // each variable lives in its own cell so closures can share it
+    pub variables: Vec<Rc<RefCell<Value>>>,
+
+    // cells captured by the closure running in this frame
+    pub upvalues: Vec<Rc<RefCell<Value>>>,
+
     pub data: Vec<Value>,
 }
 
 impl Frame {
 
-    // new frame with parameter as name
-    pub fn new(return_position: Option<usize>, args: Vec<Value>) -> Frame {
+    // new frame running the named function
+    pub fn new(function: String, return_position: Option<usize>, args: Vec<Value>) -> Frame {
 
         Frame {
+            function,
             return_position,
-            variables: args,
+            is_constructor: false,
+            is_initializer: false,
+            is_callback: false,
+            variables: args.into_iter().map(|arg| Rc::new(RefCell::new(arg))).collect(),
+            upvalues: vec![],
             data: vec![],
         }
     }
 
-    pub fn copy_stack_top(&mut self) {
-        let value = self.get_top_value_on_stack();
-        self.push_value_to_stack(value);
+    // new frame for a constructor which returns 'self' to the caller
+    pub fn new_constructor(function: String, return_position: Option<usize>, args: Vec<Value>) -> Frame {
+        Frame {
+            is_constructor: true,
+            ..Frame::new(function, return_position, args)
+        }
+    }
+
+    // new frame for the field initializer which continues into the constructor when it returns
+    pub fn new_initializer(function: String, return_position: Option<usize>, args: Vec<Value>) -> Frame {
+        Frame {
+            is_initializer: true,
+            ..Frame::new(function, return_position, args)
+        }
+    }
+
+    // new frame running a closure with its captured variables
+    pub fn new_closure(function: String, return_position: Option<usize>, args: Vec<Value>, upvalues: Vec<Rc<RefCell<Value>>>) -> Frame {
+        Frame {
+            upvalues,
+            ..Frame::new(function, return_position, args)
+        }
+    }
+
+    // new frame for a function called from inside an instruction
+    pub fn new_callback(function: String, return_position: Option<usize>, args: Vec<Value>, upvalues: Vec<Rc<RefCell<Value>>>) -> Frame {
+        Frame {
+            is_callback: true,
+            ..Frame::new_closure(function, return_position, args, upvalues)
+        }
     }
 
     // push a value to the stack
@@ -29,50 +77,44 @@ impl Frame {
         self.data.push(value);
     }
 
-    // push a value to a variable slot
+    // put a value in a fresh cell in a variable slot, closures holding the old cell keep it
     pub fn push_value_to_variable_slot(&mut self, slot: usize, value: Value) {
         if self.variables.len() <= slot {
-            self.variables.resize(slot + 1, value);
-        } else {
-            self.variables[slot] = value;
+            self.variables.resize_with(slot + 1, || Rc::new(RefCell::new(Value::Null)));
+        }
+        self.variables[slot] = Rc::new(RefCell::new(value));
+    }
+
+    // get the cell of a variable slot so it can be captured
+    pub fn get_variable_cell(&mut self, slot: usize) -> Rc<RefCell<Value>> {
+        if self.variables.len() <= slot {
+            self.push_value_to_variable_slot(slot, Value::Null);
         }
+        self.variables[slot].clone()
     }
 
     // move a value from the stack to a variable slot
     pub fn move_from_stack_to_variable_slot(&mut self, slot: usize) {
         let value = self.pop_value_from_stack();
-        self.push_value_to_variable_slot(slot, value);
+        *self.get_variable_cell(slot).borrow_mut() = value;
     }
 
-    // copy value from the stack to a variable slot
-    pub fn copy_from_stack_to_variable_slot(&mut self, slot: usize) {
-        let value = self.get_top_value_on_stack();
-        self.push_value_to_variable_slot(slot, value);
-    }
-
-    // copy from variable slot to stack
+    // copy from variable slot to stack, variables that have not been set yet are null
     pub fn copy_from_variable_slot_to_stack(&mut self, slot: usize) {
-        let value = self.get_variable_or_panic(slot).clone();
+        let value = self.variables.get(slot).map(|cell| cell.borrow().clone()).unwrap_or(Value::Null);
         self.push_value_to_stack(value);
     }
 
-    // return a clone of the top value on the stack
-    pub fn get_top_value_on_stack(&self) -> Value {
-        let value = self.data.last().expect("stack should have a value");
-        return value.clone();
-    }
-
     // pop a value from the stack
     pub fn pop_value_from_stack(&mut self) -> Value {
-        let value = self.data.pop().expect("stack should have a value");
-        return value;
+        self.data.pop().expect("stack should have a value")
     }
 
     // pop 2 values from the stack
     pub fn pop_2_values_from_stack(&mut self) -> (Value, Value) {
         let rhs = self.pop_value_from_stack();
         let lhs = self.pop_value_from_stack();
-        return (lhs, rhs);
+        (lhs, rhs)
     }
 
     // pop values from the stack
@@ -81,12 +123,7 @@ impl Frame {
         for _ in 0..count {
             values.push(self.pop_value_from_stack());
         }
-        return values;
+        values
     }
 
-    // get the value from the variable slot
-    pub fn get_variable_or_panic(&self, slot: usize) -> &Value {
-        self.variables.get(slot).expect("variable slot should exist")
-    }
-
-}
\ No newline at end of file
+}
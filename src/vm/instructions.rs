@@ -2,6 +2,7 @@
 pub enum Instruction {
 
     // Stack
+    Pop,
     PushNull,
     PushInteger(i32),
     PushFloat(f32),
@@ -12,9 +13,15 @@ pub enum Instruction {
     // Variables
     MoveToLocalVariable(usize),
     LoadLocalVariable(usize),
+    ResetLocalVariable(usize),
+
+    // Captured Variables
+    LoadUpvalue(usize),
+    StoreUpvalue(usize),
 
     // Global
     LoadGlobal(String),
+    StoreGlobal(String),
     LoadMethod(String),
 
     // Objects
@@ -29,21 +36,31 @@ pub enum Instruction {
     // Iteration
     IteratorNew,
     IteratorNext(usize, usize),
+    PairIteratorNew,
+    PairIteratorNext(usize, usize, usize),
     
     // Instructions
+    MakeClosure(String, Vec<Capture>),
     Call(usize),
     JumpForward(usize),
     JumpBackward(usize),
     JumpIfFalse(i32),
+    JumpIfFalseOrPop(usize),
+    JumpIfTrueOrPop(usize),
     Return(bool),
 
     // Operators
+    Not,
+    Negate,
     Equal,
     NotEqual,
     Add,
+    Concat(usize),
     Sub,
     Multiply,
     Divide,
+    IntegerDivide,
+    Modulo,
     Pow,
 
     // Comparison
@@ -55,4 +72,16 @@ pub enum Instruction {
     // Halt Program
     Halt(String)
 
-}
\ No newline at end of file
+}
+
+// where a closure finds a variable it captures when it is created
+#[derive(Clone, Debug)]
+pub enum Capture {
+
+    // a local variable slot of the function creating the closure
+    Local(usize),
+
+    // an upvalue the function creating the closure has captured itself
+    Upvalue(usize),
+
+}
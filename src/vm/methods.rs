@@ -0,0 +1,207 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
+use indexmap::IndexMap;
+use crate::error::OrbError;
+use crate::vm::frame::Frame;
+use crate::vm::program::Program;
+use crate::vm::value::{Key, Value};
+use crate::vm::{compare, VM};
+
+// methods built into arrays and dictionaries
+const ARRAY_METHODS: [&str; 14] = ["push", "pop", "insert", "remove", "len", "slice", "sort", "reverse", "contains", "index_of", "join", "map", "filter", "reduce"];
+const DICTIONARY_METHODS: [&str; 6] = ["keys", "values", "has", "remove", "len", "merge"];
+
+// check a value has a built in method with this name
+pub fn has_native_method(value: &Value, name: &str) -> bool {
+    match value {
+        Value::Array(_) => ARRAY_METHODS.contains(&name),
+        Value::Dictionary(_) => DICTIONARY_METHODS.contains(&name),
+        _ => false
+    }
+}
+
+impl VM {
+
+    // call a built in method, the first argument is the array or dictionary it was called on
+    pub(super) fn call_native_method(&mut self, program: &Program, frames: &mut Vec<Frame>, ip: usize, name: &str, args: Vec<Value>) -> Result<Value, OrbError> {
+
+        let mut args = args.into_iter();
+        let target = args.next().expect("method should have a target");
+        let args: Vec<Value> = args.collect();
+
+        match target {
+            Value::Array(items) => self.array_method(program, frames, ip, items, name, args),
+            Value::Dictionary(items) => dictionary_method(items, name, args),
+            value => Err(OrbError::runtime(format!("method {:?} does not exist on {}", name, value.type_name())))
+        }
+    }
+
+    //==============================================================================================
+    // ARRAY
+
+    fn array_method(&mut self, program: &Program, frames: &mut Vec<Frame>, ip: usize, items: Rc<RefCell<Vec<Value>>>, name: &str, args: Vec<Value>) -> Result<Value, OrbError> {
+
+        match (name, args.as_slice()) {
+
+            ("push", [value]) => {
+                items.borrow_mut().push(value.clone());
+                Ok(Value::Null)
+            },
+
+            // popping an empty array gives null
+            ("pop", []) => Ok(items.borrow_mut().pop().unwrap_or(Value::Null)),
+
+            ("insert", [Value::Integer(index), value]) => {
+                let mut items = items.borrow_mut();
+                let index = array_index(*index, items.len() + 1)?;
+                items.insert(index, value.clone());
+                Ok(Value::Null)
+            },
+
+            ("remove", [Value::Integer(index)]) => {
+                let mut items = items.borrow_mut();
+                let index = array_index(*index, items.len())?;
+                Ok(items.remove(index))
+            },
+
+            ("len", []) => Ok(Value::Integer(items.borrow().len() as i32)),
+
+            // new array from start up to but not including end, which defaults to the end of the array
+            ("slice", [Value::Integer(start)]) | ("slice", [Value::Integer(start), Value::Integer(_)]) => {
+                let items = items.borrow();
+                let end = match args.get(1) {
+                    Some(Value::Integer(end)) => array_index(*end, items.len() + 1)?,
+                    _ => items.len()
+                };
+                let start = array_index(*start, end + 1)?;
+                Ok(Value::Array(Rc::new(RefCell::new(items[start..end].to_vec()))))
+            },
+
+            ("sort", []) => {
+                let mut error = None;
+                items.borrow_mut().sort_by(|lhs, rhs| compare(lhs, rhs).unwrap_or_else(|e| {
+                    error.get_or_insert(e);
+                    Ordering::Equal
+                }));
+                error.map_or(Ok(Value::Null), Err)
+            },
+
+            ("reverse", []) => {
+                items.borrow_mut().reverse();
+                Ok(Value::Null)
+            },
+
+            ("contains", [value]) => {
+                let index = self.find(program, frames, ip, &items, value)?;
+                Ok(Value::Bool(index.is_some()))
+            },
+
+            // the position of the first matching value or -1 if there is none
+            ("index_of", [value]) => {
+                let index = self.find(program, frames, ip, &items, value)?;
+                Ok(Value::Integer(index.map_or(-1, |index| index as i32)))
+            },
+
+            ("join", [Value::String(separator)]) => {
+                let values = items.borrow().clone();
+                let mut strings = vec![];
+                for value in values {
+                    strings.push(self.stringify(program, frames, ip, value)?.to_string());
+                }
+                Ok(Value::String(strings.join(separator)))
+            },
+
+            ("map", [function]) => {
+                let values = items.borrow().clone();
+                let mut mapped = vec![];
+                for value in values {
+                    mapped.push(self.call_function(program, frames, ip, function.clone(), vec![value])?);
+                }
+                Ok(Value::Array(Rc::new(RefCell::new(mapped))))
+            },
+
+            ("filter", [function]) => {
+                let values = items.borrow().clone();
+                let mut kept = vec![];
+                for value in values {
+                    if self.call_function(program, frames, ip, function.clone(), vec![value.clone()])?.is_truthy() {
+                        kept.push(value);
+                    }
+                }
+                Ok(Value::Array(Rc::new(RefCell::new(kept))))
+            },
+
+            // fold the values into one starting from the initial value
+            ("reduce", [function, initial]) => {
+                let values = items.borrow().clone();
+                let mut result = initial.clone();
+                for value in values {
+                    result = self.call_function(program, frames, ip, function.clone(), vec![result, value])?;
+                }
+                Ok(result)
+            },
+
+            _ => Err(wrong_arguments("array", name, &args))
+        }
+    }
+
+    // find the position of a value in an array, objects are matched with their __eq method
+    fn find(&mut self, program: &Program, frames: &mut Vec<Frame>, ip: usize, items: &Rc<RefCell<Vec<Value>>>, value: &Value) -> Result<Option<usize>, OrbError> {
+        let values = items.borrow().clone();
+        for (index, item) in values.into_iter().enumerate() {
+            if self.equals(program, frames, ip, (item, value.clone()))? {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+
+}
+
+//==================================================================================================
+// DICTIONARY
+
+fn dictionary_method(items: Rc<RefCell<IndexMap<Key, Value>>>, name: &str, args: Vec<Value>) -> Result<Value, OrbError> {
+
+    match (name, args.as_slice()) {
+
+        ("keys", []) => Ok(Value::Array(Rc::new(RefCell::new(items.borrow().keys().cloned().map(Value::from).collect())))),
+
+        ("values", []) => Ok(Value::Array(Rc::new(RefCell::new(items.borrow().values().cloned().collect())))),
+
+        ("has", [key]) => {
+            let key = Key::try_from(key.clone()).map_err(OrbError::runtime)?;
+            Ok(Value::Bool(items.borrow().contains_key(&key)))
+        },
+
+        // removing a missing key gives null, the other keys keep their order
+        ("remove", [key]) => {
+            let key = Key::try_from(key.clone()).map_err(OrbError::runtime)?;
+            Ok(items.borrow_mut().shift_remove(&key).unwrap_or(Value::Null))
+        },
+
+        ("len", []) => Ok(Value::Integer(items.borrow().len() as i32)),
+
+        // copy the entries of another dictionary into this one, replacing keys that already exist
+        ("merge", [Value::Dictionary(other)]) => {
+            let other = other.borrow().clone();
+            items.borrow_mut().extend(other);
+            Ok(Value::Null)
+        },
+
+        _ => Err(wrong_arguments("dictionary", name, &args))
+    }
+}
+
+// check an index is inside an array of the given length
+fn array_index(index: i32, len: usize) -> Result<usize, OrbError> {
+    usize::try_from(index).ok()
+        .filter(|index| *index < len)
+        .ok_or_else(|| OrbError::runtime(format!("array index {} is out of bounds", index)))
+}
+
+fn wrong_arguments(type_name: &str, name: &str, args: &[Value]) -> OrbError {
+    let types: Vec<&str> = args.iter().map(|arg| arg.type_name()).collect();
+    OrbError::runtime(format!("{} method {:?} can not be called with ({})", type_name, name, types.join(", ")))
+}
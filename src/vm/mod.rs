@@ -1,24 +1,55 @@
 use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::rc::Rc;
-use log::{debug, info, trace, warn};
+use indexmap::IndexMap;
+use log::{info, trace};
+use crate::error::{OrbError, StackFrame};
 use crate::vm::counter::IndexedCounter;
 use crate::vm::frame::Frame;
-use crate::vm::instructions::Instruction;
+use crate::vm::instructions::{Capture, Instruction};
+use crate::vm::methods::has_native_method;
 use crate::vm::program::Program;
-use crate::vm::value::Value;
+use crate::vm::value::{Closure, Key, Static, Value};
 
-pub mod program;
-pub mod instructions;
-pub mod value;
+pub(crate) mod program;
+pub(crate) mod instructions;
+pub(crate) mod value;
 mod frame;
 mod counter;
+mod methods;
 
-pub(crate) struct VM {
+// most calls that can be running at once, which stops endless recursion, frames are kept on the heap
+// so this is only there to fail before running out of memory
+const MAX_CALL_DEPTH: usize = 100_000;
+
+// a callback such as __tostring runs the vm again inside the instruction that called it and uses
+// the native stack, so far fewer of them can be running at once
+const MAX_CALLBACK_DEPTH: usize = 20;
+
+// operator methods that give the same answer with the values swapped
+const COMMUTATIVE_METHODS: [&str; 2] = ["__add", "__mul"];
+
+// a function provided by the host that can be called from a script
+pub type BuiltinFunction = Box<dyn FnMut(Vec<Value>) -> Result<Option<Value>, String>>;
+
+pub struct VM {
 
     // a vec of callbacks
-    builtin_functions: HashMap<String, Box<dyn FnMut(Vec<Value>) -> Option<Value>>>,
+    builtin_functions: HashMap<String, BuiltinFunction>,
+
+    // values of the variables declared at the top of a script
+    globals: HashMap<String, Value>,
+
+    // callbacks currently running inside other instructions
+    callbacks: usize,
+
+}
 
+impl Default for VM {
+    fn default() -> Self {
+        VM::new()
+    }
 }
 
 impl VM {
@@ -26,55 +57,80 @@ impl VM {
     pub fn new() -> VM {
         VM {
             builtin_functions: Default::default(),
+            globals: Default::default(),
+            callbacks: 0,
         }
     }
 
     // add a callback to the vm
-    pub fn add_builtin_function(&mut self, name: &str, callback: impl FnMut(Vec<Value>) -> Option<Value> + 'static) {
+    pub fn add_builtin_function(&mut self, name: &str, callback: impl FnMut(Vec<Value>) -> Result<Option<Value>, String> + 'static) {
         self.builtin_functions.insert(name.to_string(), Box::new(callback));
     }
 
-    pub fn execute(&mut self, program: Program, parameters: Option<Vec<Value>>, entrypoint: Option<String>) -> Result<Option<Value>, String> {
+    // run the entrypoint, main by default, and give back its return value or none if it returned null
+    pub fn execute(&mut self, program: &Program, parameters: Option<Vec<Value>>, entrypoint: Option<String>) -> Result<Option<Value>, OrbError> {
 
         info!("Running program");
 
-        let mut ip: usize;
-        let mut frames: Vec<Frame> = vec![];
-        
         // Set entrypoint or use default
         let entry = match entrypoint {
             Some(entry) => entry,
             None => String::from("main")
         };
 
-        // Set instruction pointer to entrypoint
-        if program.globals.contains_key(entry.as_str()) {
-            if let Value::FunctionPointer(i) = program.globals.get(entry.as_str()).expect("program globals should have key") {
-                ip = *i
-            } else {
-                return Err(format!("No entrypoint found: {:?}", entry));
-            }
-        } else {
-            return Err(format!("No entrypoint found: {:?}", entry));
+        self.initialize(program)?;
+
+        match self.call(program, entry.as_str(), parameters.unwrap_or(vec![]))? {
+            Value::Null => Ok(None),
+            value => Ok(Some(value))
+        }
+    }
+
+    // reset the global variables and run the top level statements of the script and its modules,
+    // execute does this itself but a host using call has to do it first
+    pub fn initialize(&mut self, program: &Program) -> Result<(), OrbError> {
+        self.globals.clear();
+        for initializer in program.initializers.iter() {
+            self.call(program, initializer.as_str(), vec![])?;
         }
+        Ok(())
+    }
+
+    // call a global function or a static class function such as "shapes.area" and give back its return value
+    pub fn call(&mut self, program: &Program, function: &str, args: Vec<Value>) -> Result<Value, OrbError> {
+
+        // Set instruction pointer to function
+        let mut ip = match program.globals.get(function) {
+            Some(Value::FunctionPointer(i)) => *i,
+            _ => return Err(OrbError::runtime(format!("No entrypoint found: {:?}", function)))
+        };
 
         // push new frame
-        frames.push(Frame::new(None, parameters.unwrap_or(vec![])));
+        let mut frames = vec![Frame::new(function.to_string(), None, args)];
 
-        trace!("entrypoint: {:?}", entry);
+        trace!("entrypoint: {:?}", function);
         trace!("globals: {:?}", program.globals);
         trace!("instructions: {:?}", program.instructions);
         trace!("== start execution ==");
 
+        // run until the function returns, errors point at the instruction that failed
+        self.run(program, &mut frames, &mut ip).map_err(|e| e.with_location(program.span_at(ip), stack_trace(program, &frames, ip)))
+    }
+
+    // run instructions from ip until the bottom frame returns and give back its return value
+    fn run(&mut self, program: &Program, frames: &mut Vec<Frame>, ip: &mut usize) -> Result<Value, OrbError> {
+
         // set current frame
         let mut frame = frames.last_mut().expect("frame should be on the stack");
         loop {
 
             // get instruction
-            let instruction = program.instructions.get(ip).expect(&*format!("instruction #{} should exist", ip));
+            let Some(instruction) = program.instructions.get(*ip) else {
+                return Err(OrbError::runtime(format!("instruction #{} does not exist", ip)));
+            };
 
             trace!("== loop ==");
-            trace!("ip: {}, instruction: {:?}", ip, instruction);
+            trace!("ip: {}, instruction: {:?}", *ip, instruction);
             trace!("globals: {:?}", program.globals);
             trace!("variables: {:?}", frame.variables);
             trace!("stack: {:?}", frame.data);
@@ -84,60 +140,84 @@ impl VM {
                 //==================================================================================
                 // STACK
 
+                Instruction::Pop => {
+                    frame.pop_value_from_stack();
+                    *ip += 1;
+                }
+
                 Instruction::PushNull => {
                     frame.push_value_to_stack(Value::Null);
-                    ip += 1;
+                    *ip += 1;
                 }
 
                 Instruction::PushInteger(value) => {
                     frame.push_value_to_stack(Value::Integer(*value));
-                    ip += 1;
+                    *ip += 1;
                 }
 
                 Instruction::PushFloat(value) => {
                     frame.push_value_to_stack(Value::Float(*value));
-                    ip += 1;
+                    *ip += 1;
                 }
 
                 Instruction::PushBool(value) => {
                     frame.push_value_to_stack(Value::Bool(*value));
-                    ip += 1;
+                    *ip += 1;
                 }
 
                 Instruction::PushString(value) => {
                     frame.push_value_to_stack(Value::String(value.clone()));
-                    ip += 1;
+                    *ip += 1;
                 }
 
                 Instruction::PushFunctionRef(value) => {
                     frame.push_value_to_stack(Value::FunctionRef(value.clone()));
-                    ip += 1;
+                    *ip += 1;
                 }
 
                 //==================================================================================
                 // CONTROL FLOW
 
                 Instruction::JumpForward(delta) => {
-                    ip += *delta as usize;
+                    *ip += *delta;
                 }
 
                 Instruction::JumpBackward(delta) => {
-                    ip -= *delta as usize;
+                    *ip -= *delta;
                 }
 
                 Instruction::JumpIfFalse(delta) => {
 
                     let b = frame.pop_value_from_stack();
 
-                    match b {
-                        Value::Bool(false) =>{
-                            if *delta > 0 {
-                                ip += *delta as usize;
-                            } else {
-                                ip -= *delta as usize;
-                            }
-                        },
-                        _ => ip += 1
+                    if b.is_truthy() {
+                        *ip += 1;
+                    } else if *delta > 0 {
+                        *ip += *delta as usize;
+                    } else {
+                        *ip -= delta.unsigned_abs() as usize;
+                    }
+                }
+
+                // leave the value on the stack and jump if it is false, otherwise discard it
+                Instruction::JumpIfFalseOrPop(delta) => {
+                    let value = frame.pop_value_from_stack();
+                    if value.is_truthy() {
+                        *ip += 1;
+                    } else {
+                        frame.push_value_to_stack(value);
+                        *ip += *delta;
+                    }
+                }
+
+                // leave the value on the stack and jump if it is true, otherwise discard it
+                Instruction::JumpIfTrueOrPop(delta) => {
+                    let value = frame.pop_value_from_stack();
+                    if value.is_truthy() {
+                        frame.push_value_to_stack(value);
+                        *ip += *delta;
+                    } else {
+                        *ip += 1;
                     }
                 }
 
@@ -148,19 +228,48 @@ impl VM {
                 // get value from stack and store in variable
                 Instruction::MoveToLocalVariable(index) => {
                     frame.move_from_stack_to_variable_slot(*index);
-                    ip += 1;
+                    *ip += 1;
                 }
 
                 // get value from variable and push onto stack
                 Instruction::LoadLocalVariable(index) => {
                     frame.copy_from_variable_slot_to_stack(*index);
-                    ip += 1;
+                    *ip += 1;
+                }
+
+                // give the variable a new cell, leaving the old one to any closures that captured it
+                Instruction::ResetLocalVariable(index) => {
+                    frame.push_value_to_variable_slot(*index, Value::Null);
+                    *ip += 1;
                 }
 
+                // get value from a captured variable and push onto stack
+                Instruction::LoadUpvalue(index) => {
+                    let value = upvalue(frame, *index)?.borrow().clone();
+                    frame.push_value_to_stack(value);
+                    *ip += 1;
+                }
+
+                // get value from stack and store in a captured variable
+                Instruction::StoreUpvalue(index) => {
+                    let value = frame.pop_value_from_stack();
+                    *upvalue(frame, *index)?.borrow_mut() = value;
+                    *ip += 1;
+                }
+
+                // global variables that have been set hide the values in the program
                 Instruction::LoadGlobal(name) => {
-                    let function_ref = program.globals.get(name).expect(&*format!("global variable {:?} should exist", name));
-                    frame.push_value_to_stack(function_ref.clone());
-                    ip += 1;
+                    let Some(value) = self.globals.get(name).or_else(|| program.globals.get(name)) else {
+                        return Err(OrbError::runtime(format!("global variable {:?} does not exist", name)));
+                    };
+                    frame.push_value_to_stack(value.clone());
+                    *ip += 1;
+                },
+
+                Instruction::StoreGlobal(name) => {
+                    let value = frame.pop_value_from_stack();
+                    self.globals.insert(name.clone(), value);
+                    *ip += 1;
                 },
 
                 //==================================================================================
@@ -169,104 +278,142 @@ impl VM {
                 Instruction::LoadMethod(name) => {
 
                     // pop object from stack
-                    let Value::Object(object) = frame.pop_value_from_stack() else {
-                        panic!("method should be called on an object");
-                    };
+                    let target = frame.pop_value_from_stack();
+
+                    // arrays and dictionaries have methods built into the vm
+                    if has_native_method(&target, name) {
+                        frame.push_value_to_stack(Value::NativeMethod(name.clone()));
+                        frame.push_value_to_stack(target);
+                        *ip += 1;
+                        continue;
+                    }
 
-                    // borrow object
-                    let borrowed_object = object.borrow();
+                    let Value::Object(object) = target else {
+                        return Err(OrbError::runtime(format!("can not call method {:?} on {}", name, target.type_name())));
+                    };
 
                     // get function ref from object
-                    let Some(function_ref) = borrowed_object.get(name) else {
-                        panic!("method should exist on object");
+                    let Some(function_ref) = object.borrow().get(name).cloned() else {
+                        return Err(OrbError::runtime(format!("method {:?} does not exist on object", name)));
                     };
 
                     // push function ref onto stack
-                    frame.push_value_to_stack(function_ref.clone());
+                    frame.push_value_to_stack(function_ref);
 
                     // push object back onto stack
                     frame.push_value_to_stack(Value::Object(object.clone()));
 
-                    ip += 1;
+                    *ip += 1;
                 },
 
+                // create a closure sharing the cells of the variables it captures
+                Instruction::MakeClosure(function, captures) => {
+
+                    let upvalues = captures.iter().map(|capture| match capture {
+                        Capture::Local(slot) => Ok(frame.get_variable_cell(*slot)),
+                        Capture::Upvalue(index) => upvalue(frame, *index),
+                    }).collect::<Result<_, OrbError>>()?;
+
+                    frame.push_value_to_stack(Value::Closure(Rc::new(Closure { function: function.clone(), upvalues })));
+
+                    *ip += 1;
+                }
+
                 Instruction::Call(arg_len) => {
 
                     // cut args from stack and then reverse order
                     let mut args = frame.pop_values_from_stack(*arg_len);
                     args.reverse();
 
-                    let fref = frame.pop_value_from_stack();
+                    // closures bring their captured variables with them
+                    let (fref, upvalues) = match frame.pop_value_from_stack() {
+                        Value::Closure(closure) => (Value::FunctionRef(closure.function.clone()), closure.upvalues.clone()),
+                        value => (value, vec![])
+                    };
+
+                    if let Value::NativeMethod(name) = fref {
+
+                        // call built in method with the array or dictionary as the first argument
+                        let result = self.call_native_method(program, frames, *ip, name.as_str(), args)?;
+                        frame = frames.last_mut().expect("frame should be on the stack");
+                        frame.push_value_to_stack(result);
+
+                        *ip += 1;
 
-                    if let Value::FunctionRef(func_name) = fref {
+                    } else if let Value::FunctionRef(func_name) = fref {
 
-                        if self.builtin_functions.contains_key(func_name.as_str()) {
+                        if let Some(callback) = self.builtin_functions.get_mut(func_name.as_str()) {
 
                             // call builtin function
-                            let callback = self.builtin_functions.get_mut(func_name.as_str()).expect("callback should exist");
-                            let result = callback(args);
+                            let result = callback(args).map_err(|e| OrbError::host(func_name.clone(), e))?;
 
                             // push result to stack
-                            if let Some(result) = result {
-                                frame.push_value_to_stack(result);
-                            }
-
-                            ip += 1;
+                            frame.push_value_to_stack(result.unwrap_or(Value::Null));
 
-                        } else if program.globals.contains_key(func_name.as_str()) {
+                            *ip += 1;
 
-                            let function_ref = program.globals.get(func_name.as_str()).expect("global function should exist");
+                        } else if let Some(function_ref) = program.globals.get(func_name.as_str()) {
 
                             // get function pointer
                             let Value::FunctionPointer(function_position) = function_ref else {
-                                panic!("function should be a function pointer");
+                                return Err(OrbError::runtime(format!("{:?} is not a function", func_name)));
                             };
 
                             // push new frame onto frames
-                            let next_ip = ip + 1;
-                            frames.push(Frame::new(Some(next_ip), args));
+                            let next_ip = *ip + 1;
+                            push_frame(frames, Frame::new_closure(func_name, Some(next_ip), args, upvalues))?;
 
                             // set current frame
                             frame = frames.last_mut().expect("frame should be on the stack");
 
                             // set instruction pointer to function
-                            ip = *function_position;
+                            *ip = *function_position;
 
                         } else {
-                            panic!("can not find function: {:?}", func_name);
+                            return Err(OrbError::runtime(format!("can not find function: {:?}", func_name)));
                         }
 
                     } else {
-                        panic!("looking for function ref but found: {:?}", fref);
+                        return Err(OrbError::runtime(format!("can not call {}", fref.type_name())));
                     };
 
                 }
 
                 Instruction::Return(has_return_value) => {
 
-                    // pop return value from stack
-                    let return_value = if *has_return_value {
+                    // pop return value from stack, constructors always return 'self'
+                    let return_value = if frame.is_constructor {
+                        frame.copy_from_variable_slot_to_stack(0);
+                        frame.pop_value_from_stack()
+                    } else if *has_return_value {
                         frame.pop_value_from_stack()
                     } else {
                         Value::Null
                     };
 
                     // if no return position, then we are at the end of the program
-                    if frame.return_position == None {
-                        return Ok(None);
+                    let Some(return_position) = frame.return_position else {
+                        return Ok(return_value);
+                    };
+
+                    // callbacks hand their result back to the instruction that called them
+                    if frame.is_callback {
+                        frames.pop();
+                        return Ok(return_value);
                     }
 
                     // set instruction back to previous location
-                    ip = frame.return_position.expect("return position should be set");
+                    *ip = return_position;
 
                     // remove last frame
+                    let is_initializer = frame.is_initializer;
                     frames.pop();
 
                     // set new current frame
                     frame = frames.last_mut().expect("frame should be on the stack");
 
-                    // push return value onto stack
-                    if *has_return_value {
+                    // push return value onto stack, the constructor starts with an empty stack after the initializer
+                    if !is_initializer {
                         frame.push_value_to_stack(return_value);
                     }
 
@@ -280,7 +427,7 @@ impl VM {
 
                     // fetch template
                     match program.globals.get(template_name) {
-                        Some(Value::Class(class_template)) => {
+                        Some(Value::Class(class_template, _)) => {
 
                             // create new object
                             let new_object = Value::Object(Rc::new(RefCell::new(class_template.clone())));
@@ -288,35 +435,40 @@ impl VM {
                             // cut args from stack
                             let mut args = frame.pop_values_from_stack(*arg_len);
 
-                            // push new object onto stack
-                            frame.push_value_to_stack(new_object.clone());
-
                             // call constructor
                             match program.globals.get(&*format!("{}.{}", template_name, template_name)) {
                                 Some(Value::FunctionPointer(function_position)) => {
 
                                     // push object into args as 'self'
-                                    args.push(new_object.clone());
+                                    args.push(new_object);
 
                                     // args are in reverse order, so reverse them
                                     args.reverse();
 
-                                    // push new frame onto frames
-                                    let next_ip = ip + 1;
-                                    frames.push(Frame::new(Some(next_ip), args));
+                                    // push new frame onto frames, the constructor leaves the object on the stack
+                                    let next_ip = *ip + 1;
+                                    let object = args[0].clone();
+                                    push_frame(frames, Frame::new_constructor(format!("{}.{}", template_name, template_name), Some(next_ip), args))?;
+
+                                    // set instruction pointer to function
+                                    *ip = *function_position;
+
+                                    // set the fields first, returning from the initializer starts the constructor
+                                    let initializer = format!("{}.<init>", template_name);
+                                    if let Some(Value::FunctionPointer(initializer_position)) = program.globals.get(&initializer) {
+                                        push_frame(frames, Frame::new_initializer(initializer, Some(*function_position), vec![object]))?;
+                                        *ip = *initializer_position;
+                                    }
 
                                     // set current frame
                                     frame = frames.last_mut().expect("frame should be on the stack");
 
-                                    // set instruction pointer to function
-                                    ip = *function_position;
-
                                 },
-                                _ => panic!("can not find constructor for class: {:?}", template_name)
+                                _ => return Err(OrbError::runtime(format!("can not find constructor for class: {:?}", template_name)))
                             }
 
                         }
-                        _ => panic!("can not find class: {:?}", template_name)
+                        _ => return Err(OrbError::runtime(format!("can not find class: {:?}", template_name)))
                     }
 
                 }
@@ -326,24 +478,20 @@ impl VM {
 
                 Instruction::CreateCollectionAsDictionary(size) => {
 
-                    let mut items = HashMap::new();
+                    let mut items = IndexMap::new();
 
-                    for _ in 0..*size {
-
-                        let value = frame.pop_value_from_stack();
-                        let key = frame.pop_value_from_stack();
+                    // take the keys and values off the stack in the order they were written
+                    let mut values = frame.pop_values_from_stack(*size * 2);
+                    values.reverse();
+                    let mut values = values.into_iter();
 
-                        match key {
-                            Value::String(key) => {
-                                items.insert(key, value);
-                            },
-                            _ => panic!("can not create dictionary with non-string key {}", key)
-                        }
+                    while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                        items.insert(Key::try_from(key).map_err(OrbError::runtime)?, value);
                     }
 
                     frame.push_value_to_stack(Value::Dictionary(Rc::new(RefCell::new(items))));
 
-                    ip += 1;
+                    *ip += 1;
                 }
 
                 Instruction::CreateCollectionAsArray(size) => {
@@ -359,7 +507,7 @@ impl VM {
 
                     frame.push_value_to_stack(Value::Array(Rc::new(RefCell::new(items))));
 
-                    ip += 1;
+                    *ip += 1;
                 }
 
                 Instruction::GetCollectionItem => {
@@ -367,38 +515,28 @@ impl VM {
                     let key = frame.pop_value_from_stack();
                     let collection = frame.pop_value_from_stack();
 
-                    match collection {
-                        Value::Array(items) => {
-                            if let Value::Integer(index) = key {
-                                let borrowed_items = items.borrow();
-                                let array_value = borrowed_items.get(index as usize).expect(format!("array index {} should exist", index).as_str());
-                                frame.push_value_to_stack(array_value.clone());
-                            } else {
-                                panic!("can not get index on non-integer {}", key)
-                            }
+                    let value = match (&collection, &key) {
+                        (Value::Array(items), Value::Integer(index)) => {
+                            items.borrow().get(*index as usize).cloned().ok_or_else(|| format!("array index {} is out of bounds", index))
                         },
-                        Value::Dictionary(items) => {
-                            if let Value::String(index) = key {
-                                let items_borrowed = items.borrow();
-                                let v2 = items_borrowed.get(index.as_str()).expect(&*format!("key '{}' should exist in dictionary", index));
-                                frame.push_value_to_stack(v2.clone());
-                            } else {
-                                panic!("can not get index on non-string {}", key)
-                            }
+                        (Value::Dictionary(items), _) => {
+                            Key::try_from(key.clone()).and_then(|index| items.borrow().get(&index).cloned().ok_or_else(|| format!("key '{}' does not exist in dictionary", key)))
                         },
-                        Value::Object(obj) => {
-                            if let Value::String(index) = key {
-                                let items_borrowed = obj.borrow();
-                                let v2 = items_borrowed.get(index.as_str()).expect(&*format!("key '{}' should exist in dictionary", index));
-                                frame.push_value_to_stack(v2.clone());
-                            } else {
-                                panic!("can not get index on non-string {}", key)
-                            }
-                        }
-                        _ => panic!("can not get index on non-collection {}", collection)
-                    }
+                        (Value::Object(items), Value::String(index)) => {
+                            items.borrow().get(index.as_str()).cloned().ok_or_else(|| format!("key '{}' does not exist in {}", index, collection.type_name()))
+                        },
+                        (Value::Class(_, statics), Value::String(index)) => match statics.get(index.as_str()) {
+                            Some(Static::Function(function)) => Ok(Value::FunctionRef(function.clone())),
+                            Some(Static::Variable(global)) => Ok(self.globals.get(global).cloned().unwrap_or(Value::Null)),
+                            None => Err(format!("key '{}' does not exist in {}", index, collection.type_name()))
+                        },
+                        (Value::Array(_), _) | (Value::Object(_), _) | (Value::Class(_, _), _) => Err(format!("can not index {} with {}", collection.type_name(), key.type_name())),
+                        _ => Err(format!("can not get index on {}", collection.type_name()))
+                    }.map_err(OrbError::runtime)?;
+
+                    frame.push_value_to_stack(value);
 
-                    ip += 1;
+                    *ip += 1;
                 }
 
                 Instruction::SetCollectionItem => {
@@ -407,27 +545,33 @@ impl VM {
                     let value = frame.pop_value_from_stack();
                     let collection = frame.pop_value_from_stack();
 
-                    match collection {
-                        Value::Array(items) => {
-                            if let Value::Integer(index) = key {
-                                items.borrow_mut()[index as usize] = value;
-                                frame.push_value_to_stack(Value::Array(items));
-                            } else {
-                                panic!("can not get index on non-integer {}", key)
-                            }
+                    match (&collection, key) {
+                        (Value::Array(items), Value::Integer(index)) => {
+                            let mut items = items.borrow_mut();
+                            let Some(item) = items.get_mut(index as usize) else {
+                                return Err(OrbError::runtime(format!("array index {} is out of bounds", index)));
+                            };
+                            *item = value;
                         },
-                        Value::Dictionary(items) | Value::Object(items) => {
-                            if let Value::String(index) = key {
-                                items.borrow_mut().insert(index, value);
-                                frame.push_value_to_stack(Value::Dictionary(items));
-                            } else {
-                                panic!("can not get index on non-string {}", key)
-                            }
-                        }
-                        _ => panic!("can not get index on non-collection {}", collection)
+                        (Value::Dictionary(items), key) => {
+                            items.borrow_mut().insert(Key::try_from(key).map_err(OrbError::runtime)?, value);
+                        },
+                        (Value::Object(items), Value::String(index)) => {
+                            items.borrow_mut().insert(index, value);
+                        },
+                        (Value::Class(_, statics), Value::String(index)) => match statics.get(index.as_str()) {
+                            Some(Static::Variable(global)) => {
+                                self.globals.insert(global.clone(), value);
+                            },
+                            _ => return Err(OrbError::runtime(format!("{:?} is not a static variable", index)))
+                        },
+                        (Value::Array(_), key) | (Value::Object(_), key) | (Value::Class(_, _), key) => {
+                            return Err(OrbError::runtime(format!("can not index {} with {}", collection.type_name(), key.type_name())));
+                        },
+                        _ => return Err(OrbError::runtime(format!("can not set index on {}", collection.type_name())))
                     }
 
-                    ip += 1;
+                    *ip += 1;
                 }
 
 
@@ -437,28 +581,28 @@ impl VM {
                 Instruction::IteratorNew => {
 
                     let Value::Integer(start) = frame.pop_value_from_stack() else {
-                        panic!("start should be an integer");
+                        return Err(OrbError::runtime(String::from("loop start should be an integer")));
                     };
 
                     let Value::Integer(step) = frame.pop_value_from_stack() else {
-                        panic!("step should be an integer");
+                        return Err(OrbError::runtime(String::from("loop step should be an integer")));
                     };
 
                     let target = frame.pop_value_from_stack();
 
-                    let end = match target {
+                    let end = match &target {
                         Value::Integer(i) => {
-                            frame.push_value_to_stack(target);
-                            i
+                            frame.push_value_to_stack(Value::Integer(*i));
+                            *i
                         },
                         Value::Array(items) => {
                             frame.push_value_to_stack(Value::Array(items.clone()));
                             items.borrow().len() as i32 - 1
                         },
-                        Value::Dictionary(items) => {
+                        Value::Dictionary(_) | Value::Object(_) => {
 
-                            // get dictionary keys and map to value string
-                            let keys = items.borrow().keys().map(|k| Value::String(k.clone())).collect::<Vec<Value>>();
+                            // get dictionary keys or object fields
+                            let (keys, _) = entries(&target).expect("target should have entries");
 
                             // get keys length
                             let keys_length = keys.len() as i32 - 1;
@@ -468,19 +612,19 @@ impl VM {
 
                             keys_length
                         },
-                        _ => panic!("can not iterate over non-integer, array or dictionary")
+                        _ => return Err(OrbError::runtime(format!("can not iterate over {}", target.type_name())))
                     };
 
                     // push counter onto stack
                     frame.push_value_to_stack(Value::Counter(IndexedCounter::new(start, step, end)));
 
-                    ip += 1;
+                    *ip += 1;
                 }
 
                 Instruction::IteratorNext(var_slot, ip_delta) => {
 
                     let Value::Counter(mut counter) = frame.pop_value_from_stack() else {
-                        panic!("invalid counter on stack");
+                        return Err(OrbError::runtime(String::from("invalid counter on stack")));
                     };
 
                     match frame.pop_value_from_stack() {
@@ -488,7 +632,7 @@ impl VM {
                         Value::Integer(i) => {
 
                             if counter.is_done() {
-                                ip += ip_delta;
+                                *ip += ip_delta;
                                 continue;
                             }
 
@@ -506,29 +650,93 @@ impl VM {
                         Value::Array(items) => {
 
                             if counter.is_done() {
-                                ip += ip_delta;
+                                *ip += ip_delta;
                                 continue;
                             }
 
-                            // get item from array
-                            let borrowed_items = items.borrow();
-                            let array_value = borrowed_items.get(counter.index as usize).expect(format!("array index {} should exist", counter.index).as_str());
+                            // get item from array, the array may have shrunk while looping
+                            let Some(array_value) = items.borrow().get(counter.index as usize).cloned() else {
+                                *ip += ip_delta;
+                                continue;
+                            };
 
                             // push value to variable slot
-                            frame.push_value_to_variable_slot(*var_slot, array_value.clone());
+                            frame.push_value_to_variable_slot(*var_slot, array_value);
 
                             // increment counter
                             counter.increment();
 
                             // push collection back onto stack
-                            frame.push_value_to_stack(Value::Array(items.clone()));
+                            frame.push_value_to_stack(Value::Array(items));
                             frame.push_value_to_stack(Value::Counter(counter));
 
                         },
-                        _ => panic!("can not iterate over this value type")
+                        target => return Err(OrbError::runtime(format!("can not iterate over {}", target.type_name())))
                     }
 
-                    ip += 1;
+                    *ip += 1;
+                }
+
+
+                // iterate over pairs, arrays give each index and item, dictionaries and objects
+                // give each key and value. the keys, values and counter are left on the stack
+                Instruction::PairIteratorNew => {
+
+                    let target = frame.pop_value_from_stack();
+
+                    let (keys, values) = match (&target, entries(&target)) {
+                        (Value::Array(items), _) => (Value::Null, items.clone()),
+                        (_, Some((keys, values))) => (Value::Array(Rc::new(RefCell::new(keys))), Rc::new(RefCell::new(values))),
+                        _ => return Err(OrbError::runtime(format!("can not iterate over {}", target.type_name())))
+                    };
+
+                    let end = values.borrow().len() as i32 - 1;
+
+                    frame.push_value_to_stack(keys);
+                    frame.push_value_to_stack(Value::Array(values));
+                    frame.push_value_to_stack(Value::Counter(IndexedCounter::new(0, 1, end)));
+
+                    *ip += 1;
+                }
+
+                Instruction::PairIteratorNext(key_slot, value_slot, ip_delta) => {
+
+                    let Value::Counter(mut counter) = frame.pop_value_from_stack() else {
+                        return Err(OrbError::runtime(String::from("invalid counter on stack")));
+                    };
+
+                    let (keys, values) = frame.pop_2_values_from_stack();
+
+                    let Value::Array(items) = &values else {
+                        return Err(OrbError::runtime(String::from("invalid iterator on stack")));
+                    };
+
+                    // get item from array, the array may have shrunk while looping
+                    let item = if counter.is_done() { None } else { items.borrow().get(counter.index as usize).cloned() };
+                    let Some(item) = item else {
+                        *ip += ip_delta;
+                        continue;
+                    };
+
+                    // arrays use the index as the key
+                    let key = match &keys {
+                        Value::Array(keys) => keys.borrow()[counter.index as usize].clone(),
+                        _ => Value::Integer(counter.index)
+                    };
+
+                    // push key and value to variable slots
+                    frame.push_value_to_variable_slot(*key_slot, key);
+                    frame.push_value_to_variable_slot(*value_slot, item);
+
+                    // increment counter
+                    counter.increment();
+
+                    // push iterator back onto stack
+                    frame.push_value_to_stack(keys);
+                    frame.push_value_to_stack(values);
+                    frame.push_value_to_stack(Value::Counter(counter));
+
+                    *ip += 1;
                 }
 
 
@@ -537,77 +745,309 @@ impl VM {
 
                 Instruction::Add => {
                     let (lhs, rhs) = frame.pop_2_values_from_stack();
-                    frame.push_value_to_stack(lhs + rhs);
-                    ip += 1;
+
+                    // objects joined to a string are turned into strings with their __tostring method
+                    let (lhs, rhs) = match (&lhs, &rhs) {
+                        (Value::String(_), Value::Object(_)) => (lhs, self.stringify(program, frames, *ip, rhs)?),
+                        (Value::Object(_), Value::String(_)) if get_method(&lhs, "__add").is_none() => (self.stringify(program, frames, *ip, lhs)?, rhs),
+                        _ => (lhs, rhs)
+                    };
+
+                    let result = self.arithmetic(program, frames, *ip, "__add", (lhs, rhs), |lhs, rhs| lhs + rhs)?;
+                    frame = frames.last_mut().expect("frame should be on the stack");
+                    frame.push_value_to_stack(result);
+                    *ip += 1;
+                }
+
+                // join values into one string, objects are turned into strings with their __tostring method
+                Instruction::Concat(count) => {
+                    let mut values = frame.pop_values_from_stack(*count);
+                    values.reverse();
+
+                    let mut result = String::new();
+                    for value in values {
+                        result.push_str(self.stringify(program, frames, *ip, value)?.to_string().as_str());
+                    }
+
+                    frame = frames.last_mut().expect("frame should be on the stack");
+                    frame.push_value_to_stack(Value::String(result));
+                    *ip += 1;
                 }
 
                 Instruction::Sub => {
-                    let (lhs, rhs) = frame.pop_2_values_from_stack();
-                    frame.push_value_to_stack(lhs - rhs);
-                    ip += 1;
+                    let operands = frame.pop_2_values_from_stack();
+                    let result = self.arithmetic(program, frames, *ip, "__sub", operands, |lhs, rhs| lhs - rhs)?;
+                    frame = frames.last_mut().expect("frame should be on the stack");
+                    frame.push_value_to_stack(result);
+                    *ip += 1;
                 }
 
                 Instruction::Multiply => {
-                    let (lhs, rhs) = frame.pop_2_values_from_stack();
-                    frame.push_value_to_stack(lhs * rhs);
-                    ip += 1;
+                    let operands = frame.pop_2_values_from_stack();
+                    let result = self.arithmetic(program, frames, *ip, "__mul", operands, |lhs, rhs| lhs * rhs)?;
+                    frame = frames.last_mut().expect("frame should be on the stack");
+                    frame.push_value_to_stack(result);
+                    *ip += 1;
                 }
 
                 Instruction::Divide => {
-                    let (lhs, rhs) = frame.pop_2_values_from_stack();
-                    frame.push_value_to_stack(lhs / rhs);
-                    ip += 1;
+                    let operands = frame.pop_2_values_from_stack();
+                    let result = self.arithmetic(program, frames, *ip, "__div", operands, |lhs, rhs| lhs / rhs)?;
+                    frame = frames.last_mut().expect("frame should be on the stack");
+                    frame.push_value_to_stack(result);
+                    *ip += 1;
+                }
+
+                Instruction::IntegerDivide => {
+                    let operands = frame.pop_2_values_from_stack();
+                    let result = self.arithmetic(program, frames, *ip, "__idiv", operands, |lhs, rhs| lhs.int_div(rhs))?;
+                    frame = frames.last_mut().expect("frame should be on the stack");
+                    frame.push_value_to_stack(result);
+                    *ip += 1;
+                }
+
+                Instruction::Modulo => {
+                    let operands = frame.pop_2_values_from_stack();
+                    let result = self.arithmetic(program, frames, *ip, "__mod", operands, |lhs, rhs| lhs % rhs)?;
+                    frame = frames.last_mut().expect("frame should be on the stack");
+                    frame.push_value_to_stack(result);
+                    *ip += 1;
                 }
 
                 Instruction::Pow => {
-                    // todo: implement
-                    ip += 1;
+                    let operands = frame.pop_2_values_from_stack();
+                    let result = self.arithmetic(program, frames, *ip, "__pow", operands, |lhs, rhs| lhs.pow(rhs))?;
+                    frame = frames.last_mut().expect("frame should be on the stack");
+                    frame.push_value_to_stack(result);
+                    *ip += 1;
+                }
+
+                Instruction::Negate => {
+                    let value = frame.pop_value_from_stack();
+                    let result = match get_method(&value, "__neg") {
+                        Some(method) => self.call_function(program, frames, *ip, method, vec![value])?,
+                        None => (-value).map_err(OrbError::runtime)?
+                    };
+                    frame = frames.last_mut().expect("frame should be on the stack");
+                    frame.push_value_to_stack(result);
+                    *ip += 1;
                 }
 
                 //==================================================================================
                 // OPERANDS
 
+                Instruction::Not => {
+                    let value = frame.pop_value_from_stack();
+                    frame.push_value_to_stack(!value);
+                    *ip += 1;
+                }
+
                 Instruction::Equal => {
-                    let (lhs, rhs) = frame.pop_2_values_from_stack();
-                    frame.push_value_to_stack(Value::Bool(lhs == rhs));
-                    ip += 1;
+                    let operands = frame.pop_2_values_from_stack();
+                    let result = self.equals(program, frames, *ip, operands)?;
+                    frame = frames.last_mut().expect("frame should be on the stack");
+                    frame.push_value_to_stack(Value::Bool(result));
+                    *ip += 1;
                 }
 
                 Instruction::NotEqual => {
-                    let (lhs, rhs) = frame.pop_2_values_from_stack();
-                    frame.push_value_to_stack(Value::Bool(lhs != rhs));
-                    ip += 1;
+                    let operands = frame.pop_2_values_from_stack();
+                    let result = self.equals(program, frames, *ip, operands)?;
+                    frame = frames.last_mut().expect("frame should be on the stack");
+                    frame.push_value_to_stack(Value::Bool(!result));
+                    *ip += 1;
                 }
 
                 Instruction::LessThan => {
-                    let (lhs, rhs) = frame.pop_2_values_from_stack();
-                    frame.push_value_to_stack(Value::Bool(lhs < rhs));
-                    ip += 1;
+                    let operands = frame.pop_2_values_from_stack();
+                    let result = self.compare(program, frames, *ip, operands, ("__lt", "__gt"), |ordering| ordering < Ordering::Equal)?;
+                    frame = frames.last_mut().expect("frame should be on the stack");
+                    frame.push_value_to_stack(Value::Bool(result));
+                    *ip += 1;
                 }
 
                 Instruction::LessThanOrEqual => {
-                    let (lhs, rhs) = frame.pop_2_values_from_stack();
-                    frame.push_value_to_stack(Value::Bool(lhs <= rhs));
-                    ip += 1;
+                    let operands = frame.pop_2_values_from_stack();
+                    let result = self.compare(program, frames, *ip, operands, ("__le", "__ge"), |ordering| ordering <= Ordering::Equal)?;
+                    frame = frames.last_mut().expect("frame should be on the stack");
+                    frame.push_value_to_stack(Value::Bool(result));
+                    *ip += 1;
                 }
 
                 Instruction::GreaterThan => {
-                    let (lhs, rhs) = frame.pop_2_values_from_stack();
-                    frame.push_value_to_stack(Value::Bool(lhs > rhs));
-                    ip += 1;
+                    let operands = frame.pop_2_values_from_stack();
+                    let result = self.compare(program, frames, *ip, operands, ("__gt", "__lt"), |ordering| ordering > Ordering::Equal)?;
+                    frame = frames.last_mut().expect("frame should be on the stack");
+                    frame.push_value_to_stack(Value::Bool(result));
+                    *ip += 1;
                 }
 
                 Instruction::GreaterThanOrEqual => {
-                    let (lhs, rhs) = frame.pop_2_values_from_stack();
-                    frame.push_value_to_stack(Value::Bool(lhs >= rhs));
-                    ip += 1;
+                    let operands = frame.pop_2_values_from_stack();
+                    let result = self.compare(program, frames, *ip, operands, ("__ge", "__le"), |ordering| ordering >= Ordering::Equal)?;
+                    frame = frames.last_mut().expect("frame should be on the stack");
+                    frame.push_value_to_stack(Value::Bool(result));
+                    *ip += 1;
+                }
+
+                Instruction::Halt(reason) => {
+                    return Err(OrbError::runtime(format!("program halted: {}", reason)));
                 }
 
-                _ => unimplemented!("instruction {:?}", instruction)
             }
 
         }
 
     }
 
+
+    //==============================================================================================
+    // CALLBACKS
+
+    // call a script function from inside an instruction and wait for its result
+    fn call_function(&mut self, program: &Program, frames: &mut Vec<Frame>, ip: usize, function: Value, args: Vec<Value>) -> Result<Value, OrbError> {
+
+        // closures bring their captured variables with them
+        let (func_name, upvalues) = match function {
+            Value::FunctionRef(func_name) => (func_name, vec![]),
+            Value::Closure(closure) => (closure.function.clone(), closure.upvalues.clone()),
+            value => return Err(OrbError::runtime(format!("can not call {}", value.type_name())))
+        };
+
+        // call builtin function
+        if let Some(callback) = self.builtin_functions.get_mut(func_name.as_str()) {
+            let result = callback(args).map_err(|e| OrbError::host(func_name.clone(), e))?;
+            return Ok(result.unwrap_or(Value::Null));
+        }
+
+        // get function pointer
+        let Some(Value::FunctionPointer(function_position)) = program.globals.get(func_name.as_str()) else {
+            return Err(OrbError::runtime(format!("can not find function: {:?}", func_name)));
+        };
+
+        if self.callbacks >= MAX_CALLBACK_DEPTH {
+            return Err(OrbError::runtime(String::from("call stack is too deep")));
+        }
+
+        // run the function until it returns, errors point at the instruction that failed inside it
+        push_frame(frames, Frame::new_callback(func_name, Some(ip + 1), args, upvalues))?;
+        let mut callback_ip = *function_position;
+        self.callbacks += 1;
+        let result = self.run(program, frames, &mut callback_ip).map_err(|e| e.with_location(program.span_at(callback_ip), stack_trace(program, frames, callback_ip)));
+        self.callbacks -= 1;
+        result
+    }
+
+    // apply an arithmetic operator, calling the method for it when the left value is an object that has one,
+    // + and * do not care about order so 2 * v is answered by v.__mul(2), the other operators fail instead
+    fn arithmetic(&mut self, program: &Program, frames: &mut Vec<Frame>, ip: usize, method: &str, operands: (Value, Value), operator: fn(Value, Value) -> Result<Value, String>) -> Result<Value, OrbError> {
+        let (lhs, rhs) = operands;
+        if let Some(function) = get_method(&lhs, method) {
+            self.call_function(program, frames, ip, function, vec![lhs, rhs])
+        } else if let Some(function) = get_method(&rhs, method).filter(|_| COMMUTATIVE_METHODS.contains(&method)) {
+            self.call_function(program, frames, ip, function, vec![rhs, lhs])
+        } else {
+            operator(lhs, rhs).map_err(OrbError::runtime)
+        }
+    }
+
+    // check two values are equal using the __eq method of either value if it is an object that has one
+    fn equals(&mut self, program: &Program, frames: &mut Vec<Frame>, ip: usize, operands: (Value, Value)) -> Result<bool, OrbError> {
+        let (lhs, rhs) = operands;
+        if let Some(method) = get_method(&lhs, "__eq") {
+            Ok(self.call_function(program, frames, ip, method, vec![lhs, rhs])?.is_truthy())
+        } else if let Some(method) = get_method(&rhs, "__eq") {
+            Ok(self.call_function(program, frames, ip, method, vec![rhs, lhs])?.is_truthy())
+        } else {
+            Ok(lhs == rhs)
+        }
+    }
+
+    // compare two values, an object on the left uses the first method and an object on the right
+    // uses the second method with the values swapped, so a > b can be answered by b.__lt(a)
+    fn compare(&mut self, program: &Program, frames: &mut Vec<Frame>, ip: usize, operands: (Value, Value), methods: (&str, &str), test: fn(Ordering) -> bool) -> Result<bool, OrbError> {
+        let (lhs, rhs) = operands;
+        if let Some(method) = get_method(&lhs, methods.0) {
+            Ok(self.call_function(program, frames, ip, method, vec![lhs, rhs])?.is_truthy())
+        } else if let Some(method) = get_method(&rhs, methods.1) {
+            Ok(self.call_function(program, frames, ip, method, vec![rhs, lhs])?.is_truthy())
+        } else {
+            Ok(test(compare(&lhs, &rhs)?))
+        }
+    }
+
+    // turn an object into a string with its __tostring method, other values are left alone
+    fn stringify(&mut self, program: &Program, frames: &mut Vec<Frame>, ip: usize, value: Value) -> Result<Value, OrbError> {
+        match get_method(&value, "__tostring") {
+            Some(method) => self.call_function(program, frames, ip, method, vec![value]),
+            None => Ok(value)
+        }
+    }
+}
+
+// the keys and values of a dictionary or object, objects only give their fields and not their methods
+fn entries(collection: &Value) -> Option<(Vec<Value>, Vec<Value>)> {
+    match collection {
+        Value::Dictionary(items) => Some(items.borrow().iter().map(|(key, value)| (Value::from(key.clone()), value.clone())).unzip()),
+        Value::Object(items) => Some(items.borrow().iter()
+            .filter(|(_, value)| !matches!(value, Value::FunctionRef(_) | Value::Closure(_)))
+            .map(|(key, value)| (Value::String(key.clone()), value.clone()))
+            .unzip()),
+        _ => None
+    }
+}
+
+// find a method on an object
+fn get_method(value: &Value, name: &str) -> Option<Value> {
+    let Value::Object(object) = value else {
+        return None;
+    };
+    match object.borrow().get(name) {
+        Some(method @ Value::FunctionRef(_)) | Some(method @ Value::Closure(_)) => Some(method.clone()),
+        _ => None
+    }
+}
+
+// order two values or fail if they can not be compared
+fn compare(lhs: &Value, rhs: &Value) -> Result<Ordering, OrbError> {
+    lhs.partial_cmp(rhs).ok_or_else(|| OrbError::runtime(format!("can not compare {} with {}", lhs.type_name(), rhs.type_name())))
+}
+
+// a variable captured by the closure running in a frame
+fn upvalue(frame: &Frame, index: usize) -> Result<Rc<RefCell<Value>>, OrbError> {
+    frame.upvalues.get(index).cloned().ok_or_else(|| OrbError::runtime(format!("captured variable {} does not exist", index)))
+}
+
+// add a frame to the call stack, failing instead of recursing forever
+fn push_frame(frames: &mut Vec<Frame>, frame: Frame) -> Result<(), OrbError> {
+    if frames.len() >= MAX_CALL_DEPTH {
+        return Err(OrbError::runtime(String::from("call stack is too deep")));
+    }
+    frames.push(frame);
+    Ok(())
+}
+
+// describe the call stack, innermost call first
+fn stack_trace(program: &Program, frames: &[Frame], ip: usize) -> Vec<StackFrame> {
+
+    let mut trace = vec![];
+    let mut position = ip;
+
+    for frame in frames.iter().rev() {
+
+        trace.push(program.frame_at(position).unwrap_or_else(|| StackFrame {
+            function: frame.function.clone(),
+            class: None,
+            module: None,
+            span: program.span_at(position),
+        }));
+
+        // the caller is paused on the call instruction just before the return position,
+        // except for a constructor which has not started while its initializer runs
+        if let Some(return_position) = frame.return_position {
+            position = if frame.is_initializer { return_position } else { return_position - 1 };
+        }
+    }
+
+    trace
 }
@@ -1,11 +1,27 @@
 use std::collections::HashMap;
+use crate::error::{Span, StackFrame};
 use crate::vm::instructions::Instruction;
 use crate::vm::value::Value;
 
 #[derive(Clone, Debug)]
 pub struct Program {
     pub(crate) instructions: Vec<Instruction>,
-    pub globals: HashMap<String, Value>,
+    pub(crate) globals: HashMap<String, Value>,
+
+    // source location of each statement, as (first instruction, span) in instruction order
+    pub(crate) lines: Vec<(usize, Span)>,
+
+    // each function as (first instruction, description for stack traces) in instruction order
+    pub(crate) functions: Vec<(usize, StackFrame)>,
+
+    // functions running the top level statements of each module, imported modules first
+    pub(crate) initializers: Vec<String>,
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Program::new()
+    }
 }
 
 impl Program {
@@ -14,7 +30,25 @@ impl Program {
         Program {
             instructions: Vec::new(),
             globals: HashMap::new(),
+            lines: Vec::new(),
+            functions: Vec::new(),
+            initializers: Vec::new(),
         }
     }
 
-}
\ No newline at end of file
+    // find the source location of the statement an instruction belongs to
+    pub fn span_at(&self, ip: usize) -> Option<Span> {
+        let index = self.lines.partition_point(|(start, _)| *start <= ip);
+        index.checked_sub(1).map(|i| self.lines[i].1.clone())
+    }
+
+    // describe the function an instruction belongs to and where it is in the source
+    pub(crate) fn frame_at(&self, ip: usize) -> Option<StackFrame> {
+        let index = self.functions.partition_point(|(start, _)| *start <= ip);
+        index.checked_sub(1).map(|i| StackFrame {
+            span: self.span_at(ip),
+            ..self.functions[i].1.clone()
+        })
+    }
+
+}
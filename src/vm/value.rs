@@ -1,10 +1,10 @@
 use std::cell::RefCell;
 use std::cmp::Ordering;
-use std::collections::HashMap;
 use std::fmt;
 use std::fmt::{Display, Formatter};
-use std::ops::{Add, Div, Mul, Not, Sub};
+use std::ops::{Add, Div, Mul, Neg, Not, Rem, Sub};
 use std::rc::Rc;
+use indexmap::IndexMap;
 use crate::vm::counter::IndexedCounter;
 
 // Value
@@ -21,11 +21,12 @@ pub enum Value {
 
     // Collections
     Array(Rc<RefCell<Vec<Value>>>),
-    Dictionary(Rc<RefCell<HashMap<String, Value>>>),
+    Dictionary(Rc<RefCell<IndexMap<Key, Value>>>),
 
 
-    Class(HashMap<String, Value>),
-    Object(Rc<RefCell<HashMap<String, Value>>>),
+    // template for new objects and the static members shared by the whole class
+    Class(IndexMap<String, Value>, IndexMap<String, Static>),
+    Object(Rc<RefCell<IndexMap<String, Value>>>),
 
     // counter with start, step, end
     Counter(IndexedCounter),
@@ -34,21 +35,192 @@ pub enum Value {
     FunctionRef(String),
     FunctionPointer(usize),
 
+    // function with the variables it captured when it was created
+    Closure(Rc<Closure>),
+
+    // method built into arrays and dictionaries
+    NativeMethod(String),
+
+}
+
+// a static member of a class, the values of static variables are kept by the vm like other globals
+#[derive(Clone, PartialEq, Debug)]
+pub enum Static {
+
+    // name of the function
+    Function(String),
+
+    // name of the global variable holding the value
+    Variable(String),
+}
+
+// the values that can be used as dictionary keys, floats are stored by their bits so they can be hashed
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Key {
+    String(String),
+    Integer(i32),
+    Float(u32),
+    Bool(bool),
+}
+
+impl TryFrom<Value> for Key {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(Key::String(s)),
+            Value::Integer(i) => Ok(Key::Integer(i)),
+
+            // negative zero is the same key as zero
+            Value::Float(0.0) => Ok(Key::Float(0.0f32.to_bits())),
+            Value::Float(f) => Ok(Key::Float(f.to_bits())),
+
+            Value::Bool(b) => Ok(Key::Bool(b)),
+            value => Err(format!("can not use {} as a dictionary key", value.type_name()))
+        }
+    }
+}
+
+impl From<Key> for Value {
+    fn from(key: Key) -> Self {
+        match key {
+            Key::String(s) => Value::String(s),
+            Key::Integer(i) => Value::Integer(i),
+            Key::Float(bits) => Value::Float(f32::from_bits(bits)),
+            Key::Bool(b) => Value::Bool(b),
+        }
+    }
+}
+
+// a lambda and the cells of the variables it captured from the functions around it
+pub struct Closure {
+    pub function: String,
+    pub upvalues: Vec<Rc<RefCell<Value>>>,
+}
+
+// closures are the same when they run the same function over the same captured cells,
+// comparing cell contents could recurse forever when a closure captures itself
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        self.function == other.function
+            && self.upvalues.len() == other.upvalues.len()
+            && self.upvalues.iter().zip(other.upvalues.iter()).all(|(a, b)| Rc::ptr_eq(a, b))
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Closure({}, {} upvalues)", self.function, self.upvalues.len())
+    }
+}
+
+impl Value {
+
+    // only false and null are treated as false in conditions
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Bool(false) | Value::Null)
+    }
+
+    // name of the value type for use in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Integer(_) => "integer",
+            Value::Pointer(_) => "pointer",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "boolean",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Dictionary(_) => "dictionary",
+            Value::Class(_, _) => "class",
+            Value::Object(_) => "object",
+            Value::Counter(_) => "counter",
+            Value::FunctionRef(_) | Value::FunctionPointer(_) | Value::Closure(_) | Value::NativeMethod(_) => "function",
+        }
+    }
+
 }
 
 impl Display for Value {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Value::String(string) => write!(f, "{string}"),
+            value => value.write(f, &mut vec![])
+        }
+    }
+}
+
+impl Value {
+
+    // write a value the way it looks inside a collection, strings are quoted and
+    // collections that contain themselves are cut short with ...
+    fn write(&self, f: &mut Formatter, parents: &mut Vec<*const ()>) -> fmt::Result {
         match self {
             Value::Null => write!(f, "null"),
             Value::Integer(num) => write!(f, "{num}"),
+            Value::Pointer(pointer) => write!(f, "pointer {pointer}"),
             Value::Float(num) => write!(f, "{num}"),
             Value::Bool(b) => write!(f, "{b}"),
-            Value::String(string) => write!(f, "{string}"),
-            Value::Array(_val) => write!(f, "Array"),
+            Value::String(string) => write!(f, "{string:?}"),
+            Value::Array(items) => Value::write_nested(f, Rc::as_ptr(items) as *const (), parents, |f, parents| {
+                write!(f, "[")?;
+                for (index, item) in items.borrow().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.write(f, parents)?;
+                }
+                write!(f, "]")
+            }),
+            Value::Dictionary(items) => Value::write_nested(f, Rc::as_ptr(items) as *const (), parents, |f, parents| {
+                write!(f, "{{")?;
+                for (index, (key, value)) in items.borrow().iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    Value::from(key.clone()).write(f, parents)?;
+                    write!(f, ": ")?;
+                    value.write(f, parents)?;
+                }
+                write!(f, "}}")
+            }),
+            Value::Class(template, _) => Value::write_fields(f, "class", template, parents),
+            Value::Object(fields) => Value::write_nested(f, Rc::as_ptr(fields) as *const (), parents, |f, parents| {
+                Value::write_fields(f, "object", &fields.borrow(), parents)
+            }),
+            Value::Counter(_) => write!(f, "counter"),
             Value::FunctionRef(i) => write!(f, "{i}"),
-            _ => write!(f, "todo for {:?}", self),
+            Value::FunctionPointer(pointer) => write!(f, "function {pointer}"),
+            Value::Closure(closure) => write!(f, "{}", closure.function),
+            Value::NativeMethod(name) => write!(f, "{name}"),
         }
     }
+
+    // write a collection unless it is already being written further up
+    fn write_nested(f: &mut Formatter, pointer: *const (), parents: &mut Vec<*const ()>, write: impl FnOnce(&mut Formatter, &mut Vec<*const ()>) -> fmt::Result) -> fmt::Result {
+        if parents.contains(&pointer) {
+            return write!(f, "...");
+        }
+        parents.push(pointer);
+        let result = write(f, parents);
+        parents.pop();
+        result
+    }
+
+    // write the fields of a class or object, methods are left out
+    fn write_fields(f: &mut Formatter, kind: &str, fields: &IndexMap<String, Value>, parents: &mut Vec<*const ()>) -> fmt::Result {
+        write!(f, "{} {{", kind)?;
+        let fields = fields.iter().filter(|(_, value)| !matches!(value, Value::FunctionRef(_) | Value::Closure(_)));
+        for (index, (name, value)) in fields.enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: ", name)?;
+            value.write(f, parents)?;
+        }
+        write!(f, "}}")
+    }
+
 }
 
 // Value Comparison
@@ -57,22 +229,23 @@ impl PartialOrd for Value {
         match (self, rhs) {
             (Value::Integer(v1), Value::Integer(v2)) => v1.partial_cmp(v2),
             (Value::Float(v1), Value::Float(v2)) => v1.partial_cmp(v2),
-            _ => unreachable!("can not subtract values")
+            (Value::String(v1), Value::String(v2)) => v1.partial_cmp(v2),
+            _ => None
         }
     }
 }
 
 // Value Subtraction
 impl Sub for Value {
-    type Output = Value;
+    type Output = Result<Value, String>;
 
     fn sub(self, rhs: Value) -> <Self as Sub<Value>>::Output {
         match (self, rhs) {
-            (Value::Integer(v1), Value::Integer(v2)) => Value::Integer(v1 - v2),
-            (Value::Integer(v1), Value::Float(v2)) => Value::Float(v1 as f32 - v2),
-            (Value::Float(v1), Value::Integer(v2)) => Value::Float(v1 - v2 as f32),
-            (Value::Float(v1), Value::Float(v2)) => Value::Float(v1 - v2),
-            _ => unreachable!("can not subtract values")
+            (Value::Integer(v1), Value::Integer(v2)) => Ok(Value::Integer(v1.wrapping_sub(v2))),
+            (Value::Integer(v1), Value::Float(v2)) => Ok(Value::Float(v1 as f32 - v2)),
+            (Value::Float(v1), Value::Integer(v2)) => Ok(Value::Float(v1 - v2 as f32)),
+            (Value::Float(v1), Value::Float(v2)) => Ok(Value::Float(v1 - v2)),
+            (v1, v2) => Err(format!("can not subtract {} from {}", v2.type_name(), v1.type_name()))
         }
     }
 
@@ -81,66 +254,131 @@ impl Sub for Value {
 
 // Value Addition
 impl Add for Value {
-    type Output = Value;
+    type Output = Result<Value, String>;
 
     fn add(self, rhs: Value) -> <Self as Add<Value>>::Output {
         match (self, rhs) {
 
             // add integers together
-            (Value::Integer(v1), Value::Integer(v2)) => Value::Integer(v1 + v2),
-            (Value::Integer(v1), Value::Float(v2)) => Value::Float(v1 as f32 + v2),
-            (Value::Integer(v1), Value::String(v2)) => Value::String(v1.to_string().add(v2.as_str())),
+            (Value::Integer(v1), Value::Integer(v2)) => Ok(Value::Integer(v1.wrapping_add(v2))),
+            (Value::Integer(v1), Value::Float(v2)) => Ok(Value::Float(v1 as f32 + v2)),
+            (Value::Integer(v1), Value::String(v2)) => Ok(Value::String(v1.to_string().add(v2.as_str()))),
 
             // add floats together
-            (Value::Float(v1), Value::Integer(v2)) => Value::Float(v1 + v2 as f32),
-            (Value::Float(v1), Value::Float(v2)) => Value::Float(v1 + v2),
+            (Value::Float(v1), Value::Integer(v2)) => Ok(Value::Float(v1 + v2 as f32)),
+            (Value::Float(v1), Value::Float(v2)) => Ok(Value::Float(v1 + v2)),
 
             // add strings together
-            (Value::String(v1), Value::String(v2))  => Value::String(v1.add(v2.as_str())),
-            (Value::String(v1), Value::Bool(v2)) => Value::String(v1.add(v2.to_string().as_str())),
-            (Value::String(v1), Value::Integer(v2)) => Value::String(v1.add(v2.to_string().as_str())),
-            (Value::String(v1), Value::Float(v2)) => Value::String(v1.add(v2.to_string().as_str())),
+            (Value::String(v1), Value::String(v2))  => Ok(Value::String(v1.add(v2.as_str()))),
+            (Value::String(v1), Value::Bool(v2)) => Ok(Value::String(v1.add(v2.to_string().as_str()))),
+            (Value::String(v1), Value::Integer(v2)) => Ok(Value::String(v1.add(v2.to_string().as_str()))),
+            (Value::String(v1), Value::Float(v2)) => Ok(Value::String(v1.add(v2.to_string().as_str()))),
 
             // add arrays together
             (Value::Array(v1), Value::Array(v2)) => {
-                v1.borrow_mut().extend(v2.borrow().iter().cloned());
-                Value::Array(v1)
+                let items = v2.borrow().clone();
+                v1.borrow_mut().extend(items);
+                Ok(Value::Array(v1))
             },
 
             // add booleans together but only true + true = true
-            (Value::Bool(v1), Value::Bool(v2)) => Value::Bool(v1 && v2),
+            (Value::Bool(v1), Value::Bool(v2)) => Ok(Value::Bool(v1 && v2)),
 
-            _ => unreachable!("can not add values")
+            (v1, v2) => Err(format!("can not add {} to {}", v2.type_name(), v1.type_name()))
         }
     }
 }
 
 // Value Multiplication
 impl Mul for Value {
-    type Output = Value;
+    type Output = Result<Value, String>;
 
     fn mul(self, rhs: Value) -> <Self as Mul<Value>>::Output {
         match (self, rhs) {
-            (Value::Integer(v1), Value::Integer(v2)) => Value::Integer(v1 * v2),
-            (Value::Integer(v1), Value::Float(v2)) => Value::Float(v1 as f32 * v2),
-            (Value::Float(v1), Value::Integer(v2)) => Value::Float(v1 * v2 as f32),
-            (Value::Float(v1), Value::Float(v2)) => Value::Float(v1 * v2),
-            _ => unreachable!("can not multiply values")
+            (Value::Integer(v1), Value::Integer(v2)) => Ok(Value::Integer(v1.wrapping_mul(v2))),
+            (Value::Integer(v1), Value::Float(v2)) => Ok(Value::Float(v1 as f32 * v2)),
+            (Value::Float(v1), Value::Integer(v2)) => Ok(Value::Float(v1 * v2 as f32)),
+            (Value::Float(v1), Value::Float(v2)) => Ok(Value::Float(v1 * v2)),
+            (v1, v2) => Err(format!("can not multiply {} by {}", v1.type_name(), v2.type_name()))
         }
     }
 }
 
 // Value Division
 impl Div for Value {
-    type Output = Value;
+    type Output = Result<Value, String>;
 
     fn div(self, rhs: Value) -> <Self as Div<Value>>::Output {
         match (self, rhs) {
-            (Value::Integer(v1), Value::Integer(v2)) => Value::Integer(v1 / v2),
-            (Value::Integer(v1), Value::Float(v2)) => Value::Float(v1 as f32 / v2),
-            (Value::Float(v1), Value::Integer(v2)) => Value::Float(v1 / v2 as f32),
-            (Value::Float(v1), Value::Float(v2)) => Value::Float(v1 / v2),
-            _ => unreachable!("can not divide values")
+            (Value::Integer(_), Value::Integer(0)) => Err(String::from("division by zero")),
+            (Value::Integer(v1), Value::Integer(v2)) => Ok(Value::Integer(v1.wrapping_div(v2))),
+            (Value::Integer(v1), Value::Float(v2)) => Ok(Value::Float(v1 as f32 / v2)),
+            (Value::Float(v1), Value::Integer(v2)) => Ok(Value::Float(v1 / v2 as f32)),
+            (Value::Float(v1), Value::Float(v2)) => Ok(Value::Float(v1 / v2)),
+            (v1, v2) => Err(format!("can not divide {} by {}", v1.type_name(), v2.type_name()))
+        }
+    }
+}
+
+// Value Modulo, the result takes the sign of the divisor
+impl Rem for Value {
+    type Output = Result<Value, String>;
+
+    fn rem(self, rhs: Value) -> <Self as Rem<Value>>::Output {
+        match (self, rhs) {
+            (Value::Integer(_), Value::Integer(0)) => Err(String::from("modulo by zero")),
+            (Value::Integer(v1), Value::Integer(v2)) => {
+                let r = v1.wrapping_rem(v2);
+                Ok(Value::Integer(if r != 0 && (r < 0) != (v2 < 0) { r + v2 } else { r }))
+            },
+            (Value::Integer(v1), Value::Float(v2)) => Ok(Value::Float(v1 as f32 - (v1 as f32 / v2).floor() * v2)),
+            (Value::Float(v1), Value::Integer(v2)) => Ok(Value::Float(v1 - (v1 / v2 as f32).floor() * v2 as f32)),
+            (Value::Float(v1), Value::Float(v2)) => Ok(Value::Float(v1 - (v1 / v2).floor() * v2)),
+            (v1, v2) => Err(format!("can not get modulo of {} by {}", v1.type_name(), v2.type_name()))
+        }
+    }
+}
+
+impl Value {
+
+    // Value Exponentiation, integers stay integers unless the exponent is negative
+    pub fn pow(self, rhs: Value) -> Result<Value, String> {
+        match (self, rhs) {
+            (Value::Integer(v1), Value::Integer(v2)) if v2 >= 0 => Ok(Value::Integer(v1.wrapping_pow(v2 as u32))),
+            (Value::Integer(v1), Value::Integer(v2)) => Ok(Value::Float((v1 as f32).powi(v2))),
+            (Value::Integer(v1), Value::Float(v2)) => Ok(Value::Float((v1 as f32).powf(v2))),
+            (Value::Float(v1), Value::Integer(v2)) => Ok(Value::Float(v1.powi(v2))),
+            (Value::Float(v1), Value::Float(v2)) => Ok(Value::Float(v1.powf(v2))),
+            (v1, v2) => Err(format!("can not raise {} to the power of {}", v1.type_name(), v2.type_name()))
+        }
+    }
+
+    // Value Integer Division, rounds down towards negative infinity
+    pub fn int_div(self, rhs: Value) -> Result<Value, String> {
+        match (self, rhs) {
+            (Value::Integer(_), Value::Integer(0)) => Err(String::from("division by zero")),
+            (Value::Integer(v1), Value::Integer(v2)) => {
+                let q = v1.wrapping_div(v2);
+                Ok(Value::Integer(if v1.wrapping_rem(v2) != 0 && (v1 < 0) != (v2 < 0) { q - 1 } else { q }))
+            },
+            (Value::Integer(v1), Value::Float(v2)) => Ok(Value::Float((v1 as f32 / v2).floor())),
+            (Value::Float(v1), Value::Integer(v2)) => Ok(Value::Float((v1 / v2 as f32).floor())),
+            (Value::Float(v1), Value::Float(v2)) => Ok(Value::Float((v1 / v2).floor())),
+            (v1, v2) => Err(format!("can not divide {} by {}", v1.type_name(), v2.type_name()))
+        }
+    }
+
+}
+
+// Value Arithmetic Negation
+impl Neg for Value {
+    type Output = Result<Value, String>;
+
+    fn neg(self) -> Self::Output {
+        match self {
+            Value::Integer(v) => Ok(Value::Integer(v.wrapping_neg())),
+            Value::Float(v) => Ok(Value::Float(-v)),
+            v => Err(format!("can not negate {}", v.type_name()))
         }
     }
 }
@@ -150,63 +388,108 @@ impl Not for Value {
     type Output = Value;
 
     fn not(self) -> Self::Output {
-        match self {
-            Value::Bool(true) => Value::Bool(false),
-            Value::Bool(false) => Value::Bool(true),
-            _ => Value::Bool(false),
-        }
+        Value::Bool(!self.is_truthy())
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::excessive_precision)]
 mod test {
-    use crate::vm::value::Value;
+    use crate::vm::value::{Key, Value};
 
     #[test]
     fn test_add() {
 
         // integers
-        assert_eq!(Value::Integer(2) + Value::Integer(3), Value::Integer(5));
-        assert_eq!(Value::Integer(2) + Value::Float(3.3), Value::Float(5.3));
+        assert_eq!(Value::Integer(2) + Value::Integer(3), Ok(Value::Integer(5)));
+        assert_eq!(Value::Integer(2) + Value::Float(3.3), Ok(Value::Float(5.3)));
 
         // floats
-        assert_eq!(Value::Float(2.2) + Value::Float(3.3), Value::Float(5.5));
-        assert_eq!(Value::Float(2.2) + Value::Integer(3), Value::Float(5.2));
+        assert_eq!(Value::Float(2.2) + Value::Float(3.3), Ok(Value::Float(5.5)));
+        assert_eq!(Value::Float(2.2) + Value::Integer(3), Ok(Value::Float(5.2)));
 
         // strings
-        assert_eq!(Value::String(String::from("x = ")) + Value::Integer(3), Value::String(String::from("x = 3")));
-        assert_eq!(Value::String(String::from("x = ")) + Value::Float(3.1), Value::String(String::from("x = 3.1")));
-        assert_eq!(Value::String(String::from("x = ")) + Value::Bool(true), Value::String(String::from("x = true")));
+        assert_eq!(Value::String(String::from("x = ")) + Value::Integer(3), Ok(Value::String(String::from("x = 3"))));
+        assert_eq!(Value::String(String::from("x = ")) + Value::Float(3.1), Ok(Value::String(String::from("x = 3.1"))));
+        assert_eq!(Value::String(String::from("x = ")) + Value::Bool(true), Ok(Value::String(String::from("x = true"))));
 
         // true and false booleans should return false
-        assert_eq!(Value::Bool(true) + Value::Bool(false), Value::Bool(false));
-        assert_eq!(Value::Bool(false) + Value::Bool(true), Value::Bool(false));
-        assert_eq!(Value::Bool(false) + Value::Bool(false), Value::Bool(false));
-        assert_eq!(Value::Bool(true) + Value::Bool(true), Value::Bool(true));
+        assert_eq!(Value::Bool(true) + Value::Bool(false), Ok(Value::Bool(false)));
+        assert_eq!(Value::Bool(false) + Value::Bool(true), Ok(Value::Bool(false)));
+        assert_eq!(Value::Bool(false) + Value::Bool(false), Ok(Value::Bool(false)));
+        assert_eq!(Value::Bool(true) + Value::Bool(true), Ok(Value::Bool(true)));
     }
 
     #[test]
     fn test_sub() {
-        assert_eq!(Value::Integer(7) - Value::Integer(3), Value::Integer(4));
-        assert_eq!(Value::Integer(5) - Value::Float(3.3), Value::Float(1.7));
-        assert_eq!(Value::Float(2.4) - Value::Float(1.3), Value::Float(1.1000001));
-        assert_eq!(Value::Float(5.2) - Value::Integer(3), Value::Float(2.1999998));
+        assert_eq!(Value::Integer(7) - Value::Integer(3), Ok(Value::Integer(4)));
+        assert_eq!(Value::Integer(5) - Value::Float(3.3), Ok(Value::Float(1.7)));
+        assert_eq!(Value::Float(2.4) - Value::Float(1.3), Ok(Value::Float(1.1000001)));
+        assert_eq!(Value::Float(5.2) - Value::Integer(3), Ok(Value::Float(2.1999998)));
     }
 
     #[test]
     fn test_mul() {
-        assert_eq!(Value::Integer(7) * Value::Integer(3), Value::Integer(21));
-        assert_eq!(Value::Integer(5) * Value::Float(1.1), Value::Float(5.5));
-        assert_eq!(Value::Float(2.4) * Value::Float(1.3), Value::Float(3.1200001));
-        assert_eq!(Value::Float(5.2) *  Value::Integer(3), Value::Float(15.599999));
+        assert_eq!(Value::Integer(7) * Value::Integer(3), Ok(Value::Integer(21)));
+        assert_eq!(Value::Integer(5) * Value::Float(1.1), Ok(Value::Float(5.5)));
+        assert_eq!(Value::Float(2.4) * Value::Float(1.3), Ok(Value::Float(3.1200001)));
+        assert_eq!(Value::Float(5.2) *  Value::Integer(3), Ok(Value::Float(15.599999)));
     }
 
     #[test]
     fn test_div() {
-        assert_eq!(Value::Integer(21) / Value::Integer(3), Value::Integer(7));
-        assert_eq!(Value::Integer(22) / Value::Float(1.1), Value::Float(20.0));
-        assert_eq!(Value::Float(2.4) / Value::Float(1.3), Value::Float(1.84615396));
-        assert_eq!(Value::Float(5.2) /  Value::Integer(3), Value::Float(1.7333332));
+        assert_eq!(Value::Integer(21) / Value::Integer(3), Ok(Value::Integer(7)));
+        assert_eq!(Value::Integer(22) / Value::Float(1.1), Ok(Value::Float(20.0)));
+        assert_eq!(Value::Float(2.4) / Value::Float(1.3), Ok(Value::Float(1.84615396)));
+        assert_eq!(Value::Float(5.2) /  Value::Integer(3), Ok(Value::Float(1.7333332)));
+    }
+
+    #[test]
+    fn test_rem() {
+        assert_eq!(Value::Integer(7) % Value::Integer(3), Ok(Value::Integer(1)));
+        assert_eq!(Value::Integer(-7) % Value::Integer(3), Ok(Value::Integer(2)));
+        assert_eq!(Value::Integer(7) % Value::Integer(-3), Ok(Value::Integer(-2)));
+        assert_eq!(Value::Float(5.5) % Value::Integer(2), Ok(Value::Float(1.5)));
+        assert_eq!(Value::Integer(7) % Value::Integer(0), Err(String::from("modulo by zero")));
+    }
+
+    #[test]
+    fn test_pow() {
+        assert_eq!(Value::Integer(2).pow(Value::Integer(10)), Ok(Value::Integer(1024)));
+        assert_eq!(Value::Integer(2).pow(Value::Integer(-1)), Ok(Value::Float(0.5)));
+        assert_eq!(Value::Float(1.5).pow(Value::Integer(2)), Ok(Value::Float(2.25)));
+        assert_eq!(Value::Integer(9).pow(Value::Float(0.5)), Ok(Value::Float(3.0)));
+    }
+
+    #[test]
+    fn test_int_div() {
+        assert_eq!(Value::Integer(7).int_div(Value::Integer(2)), Ok(Value::Integer(3)));
+        assert_eq!(Value::Integer(-7).int_div(Value::Integer(2)), Ok(Value::Integer(-4)));
+        assert_eq!(Value::Float(7.5).int_div(Value::Integer(2)), Ok(Value::Float(3.0)));
+        assert_eq!(Value::Integer(7).int_div(Value::Integer(0)), Err(String::from("division by zero")));
+    }
+
+    #[test]
+    fn test_neg() {
+        assert_eq!(-Value::Integer(3), Ok(Value::Integer(-3)));
+        assert_eq!(-Value::Float(-2.5), Ok(Value::Float(2.5)));
+        assert!((-Value::String(String::from("a"))).is_err());
+    }
+
+    #[test]
+    fn test_not() {
+        assert_eq!(!Value::Bool(true), Value::Bool(false));
+        assert_eq!(!Value::Bool(false), Value::Bool(true));
+        assert_eq!(!Value::Null, Value::Bool(true));
+        assert_eq!(!Value::Integer(0), Value::Bool(false));
+    }
+
+    #[test]
+    fn test_invalid_operands() {
+        assert_eq!(Value::Integer(1) / Value::Integer(0), Err(String::from("division by zero")));
+        assert!((Value::Null + Value::Integer(1)).is_err());
+        assert!((Value::String(String::from("a")) * Value::Integer(2)).is_err());
+        assert_eq!(Value::Bool(true).partial_cmp(&Value::Integer(1)), None);
     }
 
     #[test]
@@ -228,4 +511,19 @@ mod test {
         assert_eq!(Value::Float(6.1) > Value::Float(3.5), true);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_key() {
+        assert_eq!(Key::try_from(Value::String(String::from("a"))), Ok(Key::String(String::from("a"))));
+        assert_eq!(Key::try_from(Value::Integer(7)), Ok(Key::Integer(7)));
+        assert_eq!(Key::try_from(Value::Bool(true)), Ok(Key::Bool(true)));
+
+        // floats round trip through their bits and both zeros are the same key
+        assert_eq!(Value::from(Key::try_from(Value::Float(1.5)).unwrap()), Value::Float(1.5));
+        assert_eq!(Key::try_from(Value::Float(-0.0)), Key::try_from(Value::Float(0.0)));
+
+        // integers and floats are different keys
+        assert_ne!(Key::try_from(Value::Integer(1)), Key::try_from(Value::Float(1.0)));
+
+        assert_eq!(Key::try_from(Value::Null), Err(String::from("can not use null as a dictionary key")));
+    }
+}
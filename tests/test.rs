@@ -1,10 +1,18 @@
-use orbscript::run;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use orbscript::{run, Compiler, FileLoader, IndexMap, Key, OrbError, Span, Value, VM};
 
 #[test]
 fn hello_world() {
     assert_eq!(run(include_str!("scripts/hello_world.orb"), None, None).unwrap(), None);
 }
 
+#[test]
+fn comments() {
+    assert_eq!(run(include_str!("scripts/comments.orb"), None, None).unwrap(), None);
+}
+
 
 // Variables
 
@@ -28,6 +36,11 @@ fn var_string() {
     assert_eq!(run(include_str!("scripts/var_string.orb"), None, None).unwrap(), None);
 }
 
+#[test]
+fn var_string_interpolation() {
+    assert_eq!(run(include_str!("scripts/var_string_interpolation.orb"), None, None).unwrap(), None);
+}
+
 #[test]
 fn var_array() {
     assert_eq!(run(include_str!("scripts/var_array.orb"), None, None).unwrap(), None);
@@ -38,6 +51,26 @@ fn var_dictionary() {
     assert_eq!(run(include_str!("scripts/var_dictionary.orb"), None, None).unwrap(), None);
 }
 
+#[test]
+fn var_dictionary_order() {
+    assert_eq!(run(include_str!("scripts/var_dictionary_order.orb"), None, None).unwrap(), None);
+}
+
+#[test]
+fn var_dictionary_keys() {
+    assert_eq!(run(include_str!("scripts/var_dictionary_keys.orb"), None, None).unwrap(), None);
+}
+
+#[test]
+fn var_collection_methods() {
+    assert_eq!(run(include_str!("scripts/var_collection_methods.orb"), None, None).unwrap(), None);
+}
+
+#[test]
+fn var_global() {
+    assert_eq!(run(include_str!("scripts/var_global.orb"), None, None).unwrap(), None);
+}
+
 #[test]
 fn var_multi_value() {
     assert_eq!(run(include_str!("scripts/var_multi_value.orb"), None, None).unwrap(), None);
@@ -63,6 +96,33 @@ fn var_object_array() {
     assert_eq!(run(include_str!("scripts/var_object_array.orb"), None, None).unwrap(), None);
 }
 
+#[test]
+fn operator_arithmetic() {
+    assert_eq!(run(include_str!("scripts/operator_arithmetic.orb"), None, None).unwrap(), None);
+}
+
+#[test]
+fn operator_logical() {
+    assert_eq!(run(include_str!("scripts/operator_logical.orb"), None, None).unwrap(), None);
+}
+
+#[test]
+fn operator_negate() {
+    assert_eq!(run(include_str!("scripts/operator_negate.orb"), None, None).unwrap(), None);
+}
+
+#[test]
+fn operator_parentheses() {
+    assert_eq!(run(include_str!("scripts/operator_parentheses.orb"), None, None).unwrap(), None);
+}
+
+#[test]
+fn operator_deep_parentheses() {
+    // each level of parentheses is only parsed once
+    let script = format!("function main()\n    return {}1{}\nend\n", "(".repeat(200), ")".repeat(200));
+    assert_eq!(run(script.as_str(), None, None).unwrap(), Some(Value::Integer(1)));
+}
+
 // Functions
 
 #[test]
@@ -80,6 +140,16 @@ fn function_with_unused_function() {
     assert_eq!(run(include_str!("scripts/function_with_unused_function.orb"), None, None).unwrap(), None);
 }
 
+#[test]
+fn function_closure() {
+    assert_eq!(run(include_str!("scripts/function_closure.orb"), None, None).unwrap(), None);
+}
+
+#[test]
+fn function_lambda_unique() {
+    assert_eq!(run(include_str!("scripts/function_lambda_unique.orb"), None, None).unwrap(), None);
+}
+
 #[test]
 fn function_deep_call() {
     assert_eq!(run(include_str!("scripts/function_deep_call.orb"), None, None).unwrap(), None);
@@ -93,6 +163,26 @@ fn class_rect() {
     assert_eq!(run(include_str!("scripts/class_rect.orb"), None, None).unwrap(), None);
 }
 
+#[test]
+fn class_inheritance() {
+    assert_eq!(run(include_str!("scripts/class_inheritance.orb"), None, None).unwrap(), None);
+}
+
+#[test]
+fn class_field_init() {
+    assert_eq!(run(include_str!("scripts/class_field_init.orb"), None, None).unwrap(), None);
+}
+
+#[test]
+fn class_static() {
+    assert_eq!(run(include_str!("scripts/class_static.orb"), None, None).unwrap(), None);
+}
+
+#[test]
+fn class_operators() {
+    assert_eq!(run(include_str!("scripts/class_operators.orb"), None, None).unwrap(), None);
+}
+
 #[test]
 fn class_fields() {
     assert_eq!(run(include_str!("scripts/class_fields.orb"), None, None).unwrap(), None);
@@ -125,11 +215,21 @@ fn loop_for_in_dict() {
     assert_eq!(run(include_str!("scripts/loop_for_in_dict.orb"), None, None).unwrap(), None);
 }
 
+#[test]
+fn loop_for_in_pairs() {
+    assert_eq!(run(include_str!("scripts/loop_for_in_pairs.orb"), None, None).unwrap(), None);
+}
+
 #[test]
 fn loop_while() {
     assert_eq!(run(include_str!("scripts/loop_while.orb"), None, None).unwrap(), None);
 }
 
+#[test]
+fn loop_break_continue() {
+    assert_eq!(run(include_str!("scripts/loop_break_continue.orb"), None, None).unwrap(), None);
+}
+
 // IFS
 
 #[test]
@@ -152,6 +252,11 @@ fn if_else_false() {
     assert_eq!(run(include_str!("scripts/if_else_false.orb"), None, None).unwrap(), None);
 }
 
+#[test]
+fn if_elseif() {
+    assert_eq!(run(include_str!("scripts/if_elseif.orb"), None, None).unwrap(), None);
+}
+
 
 // PROGRAMS
 
@@ -163,4 +268,289 @@ fn fib_quick() {
 #[test]
 fn fib_long() {
     assert_eq!(run(include_str!("scripts/program_fib.orb"), None, Some(String::from("fib_long"))).unwrap(), None);
-}
\ No newline at end of file
+}
+
+
+// EMBEDDING
+
+#[test]
+fn embed_compile_once_run_many() {
+
+    let program = Compiler::new().compile(include_str!("scripts/embed_builtin.orb")).unwrap();
+
+    // record every value the script reports back to the host
+    let reported = Rc::new(RefCell::new(vec![]));
+    let sink = reported.clone();
+
+    let mut vm = VM::new();
+    vm.add_builtin_function("report", move |mut values| {
+        sink.borrow_mut().push(values.pop().ok_or("no value to report")?);
+        Ok(None)
+    });
+
+    assert_eq!(vm.execute(&program, None, None).unwrap(), None);
+    assert_eq!(vm.execute(&program, Some(vec![Value::Integer(7)]), Some(String::from("other"))).unwrap(), None);
+    assert_eq!(vm.execute(&program, None, None).unwrap(), None);
+
+    assert_eq!(*reported.borrow(), vec![Value::String(String::from("main")), Value::Integer(7), Value::String(String::from("main"))]);
+}
+
+#[test]
+fn embed_return_values() {
+
+    let program = Compiler::new().compile(include_str!("scripts/embed_compute.orb")).unwrap();
+    let mut vm = VM::new();
+
+    // the entrypoint result is handed back, null becomes none
+    assert_eq!(vm.execute(&program, None, None).unwrap(), Some(Value::Integer(42)));
+    assert_eq!(vm.execute(&program, None, Some(String::from("nothing"))).unwrap(), None);
+
+    // any global or static function can be called with arguments
+    assert_eq!(vm.call(&program, "add", vec![Value::Integer(2), Value::Integer(3)]).unwrap(), Value::Integer(5));
+    assert_eq!(vm.call(&program, "geometry.area", vec![Value::Integer(4), Value::Integer(5)]).unwrap(), Value::Integer(20));
+    assert_eq!(vm.call(&program, "nothing", vec![]).unwrap(), Value::Null);
+
+    let Value::Dictionary(description) = vm.call(&program, "describe", vec![Value::String(String::from("orb"))]).unwrap() else {
+        panic!("describe should return a dictionary");
+    };
+    assert_eq!(description.borrow().len(), 2);
+    assert_eq!(description.borrow().get(&Key::String(String::from("name"))), Some(&Value::String(String::from("orb"))));
+    assert_eq!(description.borrow().get(&Key::String(String::from("length"))), Some(&Value::Integer(4)));
+
+    // dictionaries can be built by the host and passed in
+    let prices = IndexMap::from([(Key::String(String::from("tea")), Value::Integer(3)), (Key::Integer(7), Value::Integer(4))]);
+    let prices = Value::Dictionary(Rc::new(RefCell::new(prices)));
+    assert_eq!(vm.call(&program, "total", vec![prices]).unwrap(), Value::Integer(7));
+
+    assert!(matches!(vm.call(&program, "missing", vec![]), Err(OrbError::RuntimeError { .. })));
+}
+
+
+
+#[test]
+fn embed_global_state() {
+
+    let program = Compiler::new().compile(include_str!("scripts/embed_state.orb")).unwrap();
+    let mut vm = VM::new();
+
+    // globals keep their values between calls once the script is initialized
+    vm.initialize(&program).unwrap();
+    assert_eq!(vm.call(&program, "add", vec![Value::Integer(5)]).unwrap(), Value::Integer(5));
+    assert_eq!(vm.call(&program, "add", vec![Value::Integer(2)]).unwrap(), Value::Integer(7));
+
+    // execute starts again from the top of the script
+    assert_eq!(vm.execute(&program, None, None).unwrap(), Some(Value::Integer(1)));
+    assert_eq!(vm.call(&program, "add", vec![Value::Integer(1)]).unwrap(), Value::Integer(2));
+    assert_eq!(vm.call(&program, "calls", vec![]).unwrap(), Value::Integer(202));
+
+    // static variables start again too and are not shared with another vm
+    let mut other = VM::new();
+    other.initialize(&program).unwrap();
+    assert_eq!(other.call(&program, "calls", vec![]).unwrap(), Value::Integer(0));
+    assert_eq!(vm.call(&program, "calls", vec![]).unwrap(), Value::Integer(202));
+}
+
+
+// MODULES
+
+#[test]
+fn module_import() {
+    let mut compiler = Compiler::new();
+    compiler.set_module_loader(FileLoader::new("tests/scripts/modules"));
+    let program = compiler.compile(include_str!("scripts/module_import.orb")).unwrap();
+    assert_eq!(VM::new().execute(&program, None, None).unwrap(), Some(Value::String(String::from("4,6 16 0 square with area 9 12 4 8 3 2"))));
+}
+
+#[test]
+fn module_import_from_memory() {
+
+    let modules = HashMap::from([
+        (String::from("greeting"), String::from("function hello(name)\n    return \"hello \" + name\nend\n")),
+        (String::from("util"), String::from("import greeting as g\nfunction shout(name)\n    return g.hello(name) + \"!\"\nend\n")),
+    ]);
+
+    let mut compiler = Compiler::new();
+    compiler.set_module_loader(modules);
+
+    // both modules use the same greeting module, which is only compiled once
+    let program = compiler.compile("import util\nimport greeting\nfunction main()\n    return util.shout(\"orb\") + greeting.hello(\"again\")\nend\n").unwrap();
+    assert_eq!(VM::new().execute(&program, None, None).unwrap(), Some(Value::String(String::from("hello orb!hello again"))));
+}
+
+// ERRORS
+
+#[test]
+fn error_unknown_variable() {
+    let error = run(include_str!("scripts/error_unknown_variable.orb"), None, None).unwrap_err();
+    assert!(matches!(error, OrbError::CompileError { .. }));
+    assert_eq!(error.message(), "variable 'b' does not exist");
+    assert_eq!(error.span(), Some(Span { line: 3, column: 5, module: None }));
+}
+
+#[test]
+fn error_parse() {
+    let error = run(include_str!("scripts/error_parse.orb"), None, None).unwrap_err();
+    assert!(matches!(error, OrbError::CompileError { .. }));
+    assert_eq!(error.message(), "expected \"then\"");
+    assert_eq!(error.span().map(|s| s.line), Some(4));
+}
+
+#[test]
+fn error_unterminated_comment() {
+    let error = run(include_str!("scripts/error_unterminated_comment.orb"), None, None).unwrap_err();
+    assert!(matches!(error, OrbError::CompileError { .. }));
+    assert_eq!(error.message(), "expected \"]]\"");
+}
+
+#[test]
+fn error_integer_overflow() {
+    let error = run(include_str!("scripts/error_integer_overflow.orb"), None, None).unwrap_err();
+    assert!(matches!(error, OrbError::CompileError { .. }));
+    assert_eq!(error.span().map(|s| s.line), Some(2));
+}
+
+#[test]
+fn error_empty_chain() {
+    let error = run(include_str!("scripts/error_empty_chain.orb"), None, None).unwrap_err();
+    assert!(matches!(error, OrbError::CompileError { .. }));
+    assert_eq!(error.span().map(|s| s.line), Some(3));
+}
+
+#[test]
+fn error_bad_interpolation() {
+    let error = run(include_str!("scripts/error_bad_interpolation.orb"), None, None).unwrap_err();
+    assert!(matches!(error, OrbError::CompileError { .. }));
+    assert_eq!(error.span().map(|s| s.line), Some(3));
+}
+
+#[test]
+fn error_endless_recursion() {
+    let error = run(include_str!("scripts/error_endless_recursion.orb"), None, None).unwrap_err();
+    assert!(matches!(error, OrbError::RuntimeError { .. }));
+    assert_eq!(error.message(), "call stack is too deep");
+    assert_eq!(error.span().map(|s| s.line), Some(2));
+}
+
+#[test]
+fn error_deep_recursion() {
+    // __tostring calls itself through the vm instead of through a script call
+    let error = run(include_str!("scripts/error_deep_recursion.orb"), None, None).unwrap_err();
+    assert!(matches!(error, OrbError::RuntimeError { .. }));
+    assert_eq!(error.message(), "call stack is too deep");
+    assert_eq!(error.span().map(|s| s.line), Some(4));
+}
+
+#[test]
+fn error_reflected_operator() {
+    // 1 - v can not be answered by v.__sub(1) so it is an error instead of the wrong answer
+    let error = run(include_str!("scripts/error_reflected_operator.orb"), None, None).unwrap_err();
+    assert!(matches!(error, OrbError::RuntimeError { .. }));
+    assert_eq!(error.message(), "can not subtract object from integer");
+    assert_eq!(error.span().map(|s| s.line), Some(14));
+}
+
+#[test]
+fn error_field_initializer() {
+    let error = run(include_str!("scripts/error_field_initializer.orb"), None, None).unwrap_err();
+    assert_eq!(error.message(), "division by zero");
+    assert_eq!(error.span().map(|s| s.line), Some(4));
+
+    let trace = error.trace().iter().map(|f| (f.class.clone(), f.function.clone(), f.span.as_ref().map(|s| s.line))).collect::<Vec<_>>();
+    assert_eq!(trace[0], (Some(String::from("point")), String::from("<init>"), Some(4)));
+    assert_eq!(trace[2], (None, String::from("main"), Some(9)));
+
+    // static variables are set before main runs
+    let error = run("class point\n    static var origin = 0\n    static var scale = 1 / 0\nend\nfunction main()\nend\n", None, None).unwrap_err();
+    assert_eq!(error.message(), "division by zero");
+    assert_eq!(error.span().map(|s| s.line), Some(3));
+}
+
+#[test]
+fn error_divide_by_zero() {
+    let error = run(include_str!("scripts/error_divide_by_zero.orb"), None, None).unwrap_err();
+    assert!(matches!(error, OrbError::RuntimeError { .. }));
+    assert_eq!(error.message(), "division by zero");
+    assert_eq!(error.span(), Some(Span { line: 2, column: 5, module: None }));
+}
+
+#[test]
+fn error_assert() {
+    let error = run(include_str!("scripts/error_assert.orb"), None, None).unwrap_err();
+    assert!(matches!(error, OrbError::HostError { ref function, .. } if function == "assertEquals"));
+    assert_eq!(error.span().map(|s| s.line), Some(2));
+}
+
+#[test]
+fn error_break_outside_loop() {
+    let error = run(include_str!("scripts/error_break_outside_loop.orb"), None, None).unwrap_err();
+    assert_eq!(error.message(), "break used outside of a loop");
+    assert_eq!(error.span().map(|s| s.line), Some(2));
+}
+
+#[test]
+fn error_cyclic_import() {
+
+    let modules = HashMap::from([
+        (String::from("a"), String::from("import b\nfunction fa()\nend\n")),
+        (String::from("b"), String::from("import a\nfunction fb()\nend\n")),
+    ]);
+
+    let mut compiler = Compiler::new();
+    compiler.set_module_loader(modules);
+
+    let error = compiler.compile("import a\nfunction main()\nend\n").unwrap_err();
+    assert!(matches!(error, OrbError::CompileError { .. }));
+    assert_eq!(error.message(), "cyclic import: a -> b -> a");
+}
+
+#[test]
+fn error_in_module() {
+
+    let modules = HashMap::from([
+        (String::from("math/util"), String::from("function boom(n)\n    return n / 0\nend\n")),
+    ]);
+
+    let mut compiler = Compiler::new();
+    compiler.set_module_loader(modules);
+
+    let program = compiler.compile("import \"math/util\"\nfunction main()\n    return util.boom(1)\nend\n").unwrap();
+    let error = VM::new().execute(&program, None, None).unwrap_err();
+
+    // functions from a module are not methods, the module is recorded separately
+    let frame = &error.trace()[0];
+    assert_eq!(frame.function, "boom");
+    assert_eq!(frame.class, None);
+    assert_eq!(frame.module.as_deref(), Some("math/util"));
+    assert_eq!(error.span(), Some(Span { line: 2, column: 5, module: Some(String::from("math/util")) }));
+    assert_eq!(frame.to_string(), "in function boom in math/util at line 2");
+    assert_eq!(error.trace()[1].to_string(), "in function main at line 3");
+}
+
+#[test]
+fn error_missing_module() {
+    let mut compiler = Compiler::new();
+    compiler.set_module_loader(HashMap::new());
+    let error = compiler.compile("import missing\nfunction main()\nend\n").unwrap_err();
+    assert_eq!(error.message(), "can not import \"missing\": module does not exist");
+}
+
+#[test]
+fn error_missing_entrypoint() {
+    let error = run(include_str!("scripts/hello_world.orb"), None, Some(String::from("missing"))).unwrap_err();
+    assert!(matches!(error, OrbError::RuntimeError { .. }));
+}
+
+#[test]
+fn error_stack_trace() {
+    let error = run(include_str!("scripts/error_stack_trace.orb"), None, None).unwrap_err();
+
+    let trace = error.trace().iter().map(|f| (f.class.clone(), f.function.clone(), f.span.as_ref().map(|s| s.line))).collect::<Vec<_>>();
+    assert_eq!(trace, vec![
+        (Some(String::from("counter")), String::from("divide"), Some(10)),
+        (None, String::from("level2"), Some(16)),
+        (None, String::from("level1"), Some(20)),
+        (None, String::from("main"), Some(25)),
+    ]);
+
+    assert!(error.to_string().ends_with("stack traceback:\n    in method counter.divide at line 10\n    in function level2 at line 16\n    in function level1 at line 20\n    in function main at line 25"));
+}